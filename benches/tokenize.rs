@@ -0,0 +1,107 @@
+//! Tokenizer and parser throughput on a synthetic `_atom_site` file
+//!
+//! ```text
+//! cargo bench --bench tokenize -- --save-baseline main
+//! cargo bench --bench tokenize -- --baseline main
+//! ```
+//! The input size in MB is taken from `CIF_BENCH_MB` (default 256).
+
+use cifparse_rs::lazy::LazyDocument;
+use cifparse_rs::{special_split, split_spans, Document};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::fmt::Write;
+
+const ATOM_SITE_HEADER: &str = "data_BENCH
+_entry.id BENCH
+loop_
+_atom_site.group_PDB
+_atom_site.id
+_atom_site.type_symbol
+_atom_site.label_atom_id
+_atom_site.label_alt_id
+_atom_site.label_comp_id
+_atom_site.label_asym_id
+_atom_site.label_entity_id
+_atom_site.label_seq_id
+_atom_site.pdbx_PDB_ins_code
+_atom_site.Cartn_x
+_atom_site.Cartn_y
+_atom_site.Cartn_z
+_atom_site.occupancy
+_atom_site.B_iso_or_equiv
+_atom_site.auth_atom_id
+_atom_site.pdbx_PDB_model_num
+";
+
+/// Build an `_atom_site` loop of roughly `megabytes` MB
+fn synthetic_cif(megabytes: usize) -> String {
+    const ATOMS: [(&str, &str); 4] = [("N", "N"), ("C", "CA"), ("C", "C"), ("O", "O")];
+    let target = megabytes << 20;
+    let mut text = String::with_capacity(target + 4096);
+    text.push_str(ATOM_SITE_HEADER);
+    let mut id = 1usize;
+    while text.len() < target {
+        let (element, atom) = ATOMS[id % 4];
+        let residue = id / 4 + 1;
+        let chain = (b'A' + (residue / 1000 % 26) as u8) as char;
+        let _ = writeln!(
+            text,
+            "ATOM {} {} {} . ALA {} 1 {} ? {:.3} {:.3} {:.3} 1.00 {:.2} \"{}'\" 1",
+            id,
+            element,
+            atom,
+            chain,
+            residue,
+            (id % 997) as f64 * 0.125 - 60.0,
+            (id % 991) as f64 * 0.25 - 120.0,
+            (id % 983) as f64 * 0.5 - 240.0,
+            (id % 89) as f64 * 0.5 + 10.0,
+            atom,
+        );
+        id += 1;
+    }
+    text
+}
+
+fn bench_tokenize(c: &mut Criterion) {
+    let megabytes = std::env::var("CIF_BENCH_MB")
+        .ok()
+        .and_then(|mb| mb.parse().ok())
+        .unwrap_or(256);
+    let text = synthetic_cif(megabytes);
+
+    let mut group = c.benchmark_group("atom_site");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(text.len() as u64));
+    group.bench_function("split_spans", |b| {
+        b.iter(|| {
+            text.lines()
+                .map(|line| split_spans(line).0.len())
+                .sum::<usize>()
+        })
+    });
+    group.bench_function("special_split", |b| {
+        b.iter(|| {
+            text.lines()
+                .map(|line| special_split(line).len())
+                .sum::<usize>()
+        })
+    });
+    group.bench_function("document", |b| {
+        b.iter(|| Document::parse(&text).blocks.len())
+    });
+    group.bench_function("lazy_coordinates", |b| {
+        b.iter(|| {
+            let lazy = LazyDocument::parse(&text);
+            let atom_site = lazy.first_block().unwrap().category("_atom_site").unwrap();
+            ["Cartn_x", "Cartn_y", "Cartn_z"]
+                .iter()
+                .map(|item| lazy.column_f64(atom_site, item).unwrap().len())
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_tokenize);
+criterion_main!(benches);
@@ -0,0 +1,387 @@
+//! Apache Arrow and Parquet export of categories (`arrow` feature)
+//! Column types come from a DDL dictionary or are inferred from the values
+
+use crate::{Category, DataBlock, Document, Value, ValueKind};
+use arrow_array::{ArrayRef, Float64Array, Int64Array, RecordBatch, StringArray};
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use parquet::errors::ParquetError;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Arrow type of a category column
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    Int64,
+    Float64,
+    Utf8,
+}
+
+/// Column types by lowercase data name (e.g. `_atom_site.cartn_x`)
+pub type TypeMap = HashMap<String, ColumnType>;
+
+impl ColumnType {
+    /// Narrowest type holding all non-null values.
+    /// Numbers with standard uncertainties stay text so the uncertainty is kept,
+    /// as do integers outside the `i64` range.
+    pub fn infer(values: &[Value]) -> ColumnType {
+        let mut column_type = None;
+        for value in values.iter().filter(|v| !v.is_null()) {
+            let value_type = match value.kind {
+                ValueKind::Integer if value.as_i64().is_some() => ColumnType::Int64,
+                ValueKind::Float => ColumnType::Float64,
+                _ => return ColumnType::Utf8,
+            };
+            column_type = Some(column_type.map_or(value_type, |t: ColumnType| t.widen(value_type)));
+        }
+        column_type.unwrap_or(ColumnType::Utf8)
+    }
+
+    /// Type for a DDL2 `_item_type.code` or DDLm `_type.contents`
+    pub fn from_type_code(code: &str) -> ColumnType {
+        match code.to_ascii_lowercase().as_str() {
+            "int" | "positive_int" | "integer" | "count" | "index" => ColumnType::Int64,
+            "float" | "real" => ColumnType::Float64,
+            _ => ColumnType::Utf8,
+        }
+    }
+
+    /// Common type of two columns (integers widen to floats, anything else to text)
+    fn widen(self, other: ColumnType) -> ColumnType {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (ColumnType::Int64, ColumnType::Float64) | (ColumnType::Float64, ColumnType::Int64) => {
+                ColumnType::Float64
+            }
+            _ => ColumnType::Utf8,
+        }
+    }
+
+    fn data_type(self) -> DataType {
+        match self {
+            ColumnType::Int64 => DataType::Int64,
+            ColumnType::Float64 => DataType::Float64,
+            ColumnType::Utf8 => DataType::Utf8,
+        }
+    }
+}
+
+/// Collect item types from the save frames of a DDL2 (mmcif_pdbx.dic) or DDLm dictionary
+pub fn dictionary_types(dictionary: &Document) -> TypeMap {
+    let mut types = TypeMap::new();
+    let frames = dictionary.blocks.iter().flat_map(|b| &b.frames);
+    for frame in frames {
+        let code = frame
+            .value("_item_type.code")
+            .or_else(|| frame.value("_type.contents"))
+            .and_then(|v| v.as_str());
+        let Some(code) = code else {
+            continue;
+        };
+        let column_type = ColumnType::from_type_code(code);
+        // DDL2 frames list child items with the parent's type under `_item.name`
+        let names: Vec<&str> = match frame.category("_item").and_then(|c| c.column("name")) {
+            Some(names) => names.iter().filter_map(|v| v.as_str()).collect(),
+            None => frame
+                .value("_definition.id")
+                .and_then(|v| v.as_str())
+                .into_iter()
+                .collect(),
+        };
+        for name in names {
+            types.insert(name.to_ascii_lowercase(), column_type);
+        }
+    }
+    types
+}
+
+impl Category {
+    /// Column types from `types`, inferring those not listed
+    fn column_types(&self, types: &TypeMap) -> Vec<ColumnType> {
+        self.items
+            .iter()
+            .zip(&self.columns)
+            .map(|(item, values)| {
+                let data_name = self.data_name(item).to_ascii_lowercase();
+                types
+                    .get(&data_name)
+                    .copied()
+                    .unwrap_or_else(|| ColumnType::infer(values))
+            })
+            .collect()
+    }
+
+    /// Arrow schema of the category, one nullable field per item
+    pub fn arrow_schema(&self, types: &TypeMap) -> Schema {
+        let fields: Vec<Field> = self
+            .items
+            .iter()
+            .zip(self.column_types(types))
+            .map(|(item, t)| Field::new(item, t.data_type(), true))
+            .collect();
+        Schema::new(fields)
+    }
+
+    /// Convert the category to a record batch; `?` and `.` become nulls
+    pub fn to_record_batch(&self, types: &TypeMap) -> Result<RecordBatch, ArrowError> {
+        let schema = Arc::new(self.arrow_schema(types));
+        let rows = self.row_count();
+        let columns = self
+            .items
+            .iter()
+            .zip(&self.columns)
+            .zip(self.column_types(types))
+            .map(|((item, values), t)| to_array(&self.data_name(item), values, rows, t))
+            .collect::<Result<Vec<_>, _>>()?;
+        RecordBatch::try_new(schema, columns)
+    }
+}
+
+/// Build one Arrow array, padding short columns with nulls
+fn to_array(
+    data_name: &str,
+    values: &[Value],
+    rows: usize,
+    column_type: ColumnType,
+) -> Result<ArrayRef, ArrowError> {
+    let cells = (0..rows).map(|row| values.get(row).and_then(|v| v.as_str()));
+    let invalid = |text: &str, what: &str| {
+        ArrowError::ParseError(format!("{}: '{}' is not {}", data_name, text, what))
+    };
+    Ok(match column_type {
+        ColumnType::Int64 => Arc::new(
+            cells
+                .map(|cell| {
+                    cell.map(|text| text.parse::<i64>().map_err(|_| invalid(text, "an integer")))
+                        .transpose()
+                })
+                .collect::<Result<Int64Array, _>>()?,
+        ),
+        ColumnType::Float64 => Arc::new(
+            cells
+                .map(|cell| {
+                    cell.map(|text| {
+                        let number = text.split('(').next().unwrap_or(text);
+                        number.parse::<f64>().map_err(|_| invalid(text, "a number"))
+                    })
+                    .transpose()
+                })
+                .collect::<Result<Float64Array, _>>()?,
+        ),
+        ColumnType::Utf8 => Arc::new(cells.collect::<StringArray>()),
+    })
+}
+
+/// Write one category of several CIF files to a single Parquet table.
+/// An `entry` column holds the data block name of each row; items missing
+/// from some files are null, and inferred types are widened across files.
+/// Category and item names match ignoring ASCII case. Files are read twice, once for the
+/// schema and once to write their tables, so only one file is held in memory at a time.
+pub fn write_parquet<P: AsRef<Path>, W: Write + Send>(
+    paths: &[P],
+    category: &str,
+    types: &TypeMap,
+    writer: W,
+) -> Result<(), ParquetError> {
+    // Union of items in first-seen order with a common type each
+    let mut items: Vec<(String, ColumnType)> = Vec::new();
+    for path in paths {
+        for (_, table) in tables(path, category)? {
+            for (item, t) in table.items.iter().zip(table.column_types(types)) {
+                match items.iter_mut().find(|(i, _)| i.eq_ignore_ascii_case(item)) {
+                    Some((_, existing)) => *existing = existing.widen(t),
+                    None => items.push((item.clone(), t)),
+                }
+            }
+        }
+    }
+
+    let mut fields = vec![Field::new("entry", DataType::Utf8, false)];
+    fields.extend(
+        items
+            .iter()
+            .map(|(item, t)| Field::new(item, t.data_type(), true)),
+    );
+    let schema = Arc::new(Schema::new(fields));
+
+    let mut parquet = ArrowWriter::try_new(writer, schema.clone(), None)?;
+    for path in paths {
+        for (entry, table) in tables(path, category)? {
+            let rows = table.row_count();
+            let mut columns: Vec<ArrayRef> =
+                vec![Arc::new(StringArray::from(vec![entry.as_str(); rows]))];
+            for (item, column_type) in &items {
+                let values = table
+                    .items
+                    .iter()
+                    .position(|i| i.eq_ignore_ascii_case(item))
+                    .map_or(&[][..], |i| &table.columns[i]);
+                columns.push(to_array(
+                    &table.data_name(item),
+                    values,
+                    rows,
+                    *column_type,
+                )?);
+            }
+            parquet.write(&RecordBatch::try_new(schema.clone(), columns)?)?;
+        }
+    }
+    parquet.close()?;
+    Ok(())
+}
+
+/// The category in each data block of a file, with the block name
+fn tables(path: impl AsRef<Path>, category: &str) -> Result<Vec<(String, Category)>, ParquetError> {
+    let doc = Document::from_path(path)?;
+    Ok(doc
+        .blocks
+        .into_iter()
+        .filter_map(|block| {
+            let DataBlock {
+                name, categories, ..
+            } = block;
+            categories
+                .into_iter()
+                .find(|c| c.name.eq_ignore_ascii_case(category))
+                .map(|c| (name, c))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    const CIF: &str = r#"data_1ABC
+loop_
+_atom_site.id
+_atom_site.type_symbol
+_atom_site.Cartn_x
+_atom_site.occupancy
+_atom_site.label_seq_id
+1 N 1.5 1.0(2) 1
+2 C -3 ? .
+"#;
+
+    #[test]
+    fn test_inferred_types() {
+        let doc = Document::parse(CIF);
+        let atom_site = doc.blocks[0].category("_atom_site").unwrap();
+        let batch = atom_site.to_record_batch(&TypeMap::new()).unwrap();
+
+        let schema = batch.schema();
+        let types: Vec<&DataType> = schema.fields().iter().map(|f| f.data_type()).collect();
+        assert_eq!(
+            types,
+            [
+                &DataType::Int64,
+                &DataType::Utf8,
+                &DataType::Float64,
+                &DataType::Utf8,
+                &DataType::Int64
+            ]
+        );
+        let x = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(x.value(1), -3.0);
+        assert!(batch.column(3).is_null(1));
+        assert!(batch.column(4).is_null(1));
+
+        let doc = Document::parse("data_x\nloop_\n_x.id\n1\n99999999999999999999\n");
+        let x = doc.blocks[0].category("_x").unwrap();
+        let batch = x.to_record_batch(&TypeMap::new()).unwrap();
+        assert_eq!(batch.schema().field(0).data_type(), &DataType::Utf8);
+    }
+
+    #[test]
+    fn test_dictionary_types() {
+        let dictionary = Document::parse(
+            r#"data_mmcif_pdbx.dic
+save__atom_site.occupancy
+_item.name '_atom_site.occupancy'
+_item_type.code float
+save_
+save__atom_site.type_symbol
+_item.name '_atom_site.type_symbol'
+_item_type.code code
+save_
+"#,
+        );
+        let types = dictionary_types(&dictionary);
+        assert_eq!(types["_atom_site.occupancy"], ColumnType::Float64);
+
+        let doc = Document::parse(CIF);
+        let atom_site = doc.blocks[0].category("_atom_site").unwrap();
+        let batch = atom_site.to_record_batch(&types).unwrap();
+        let occupancy = batch
+            .column(3)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(occupancy.value(0), 1.0);
+
+        // A value that does not match the dictionary type is an error
+        let mut types = TypeMap::new();
+        types.insert("_atom_site.type_symbol".to_string(), ColumnType::Int64);
+        assert!(atom_site.to_record_batch(&types).is_err());
+    }
+
+    #[test]
+    fn test_write_parquet() {
+        let dir = std::env::temp_dir().join(format!("cifparse-arrow-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("1abc.cif");
+        let second = dir.join("2abc.cif");
+        std::fs::write(&first, CIF).unwrap();
+        std::fs::write(
+            &second,
+            "data_2ABC\nloop_\n_ATOM_SITE.id\n_ATOM_SITE.cartn_x\n_ATOM_SITE.B_iso_or_equiv\n7 4 20.5\n",
+        )
+        .unwrap();
+        let output = dir.join("atom_site.parquet");
+        let file = std::fs::File::create(&output).unwrap();
+        write_parquet(&[&first, &second], "_atom_site", &TypeMap::new(), file).unwrap();
+
+        let reader =
+            ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&output).unwrap())
+                .unwrap()
+                .build()
+                .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 3);
+
+        let schema = batches[0].schema();
+        let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "entry",
+                "id",
+                "type_symbol",
+                "Cartn_x",
+                "occupancy",
+                "label_seq_id",
+                "B_iso_or_equiv"
+            ]
+        );
+        // Integer `4` in the second file widens with the first file's floats
+        assert_eq!(schema.field(3).data_type(), &DataType::Float64);
+        let x: Vec<Option<f64>> = batches
+            .iter()
+            .flat_map(|b| {
+                let column = b.column(3).as_any().downcast_ref::<Float64Array>();
+                column.unwrap().iter().collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(x, [Some(1.5), Some(-3.0), Some(4.0)]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+//! BinaryCIF writer
+//! Encodes a document as MessagePack: integer columns as Int32 arrays, others as string arrays
+
+use crate::{Category, DataBlock, Document, Value, ValueKind};
+use rmp::encode::{write_array_len, write_bin, write_map_len, write_nil, write_str, write_uint};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// `ByteArray` type code of little-endian `i32` data
+const INT32: u64 = 3;
+/// `ByteArray` type code of `u8` data
+const UINT8: u64 = 4;
+
+/// Write a document as BinaryCIF.
+/// `?` and `.` are kept apart in each column's mask; save frames are not written.
+pub fn write_bcif<W: Write>(doc: &Document, writer: &mut W) -> io::Result<()> {
+    write_map_len(writer, 3)?;
+    write_str(writer, "version")?;
+    write_str(writer, "0.3.0")?;
+    write_str(writer, "encoder")?;
+    write_str(writer, concat!("cifparse-rs ", env!("CARGO_PKG_VERSION")))?;
+    write_str(writer, "dataBlocks")?;
+    write_array_len(writer, doc.blocks.len() as u32)?;
+    for block in &doc.blocks {
+        write_block(writer, block)?;
+    }
+    Ok(())
+}
+
+fn write_block<W: Write>(writer: &mut W, block: &DataBlock) -> io::Result<()> {
+    write_map_len(writer, 2)?;
+    write_str(writer, "header")?;
+    write_str(writer, &block.name)?;
+    write_str(writer, "categories")?;
+    write_array_len(writer, block.categories.len() as u32)?;
+    for category in &block.categories {
+        write_map_len(writer, 3)?;
+        write_str(writer, "name")?;
+        write_str(writer, &category.name)?;
+        write_str(writer, "rowCount")?;
+        write_uint(writer, category.row_count() as u64)?;
+        write_str(writer, "columns")?;
+        write_array_len(writer, category.items.len() as u32)?;
+        for (item, column) in category.items.iter().zip(&category.columns) {
+            write_column(writer, category, item, column)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_column<W: Write>(
+    writer: &mut W,
+    category: &Category,
+    item: &str,
+    column: &[Value],
+) -> io::Result<()> {
+    let rows = category.row_count();
+    let value = |row: usize| column.get(row).filter(|v| !v.is_null());
+    write_map_len(writer, 3)?;
+    write_str(writer, "name")?;
+    write_str(writer, item)?;
+
+    write_str(writer, "data")?;
+    let integers: Option<Vec<i32>> = (0..rows)
+        .map(|row| match value(row) {
+            Some(v) => as_i32(v),
+            None => Some(0),
+        })
+        .collect();
+    match integers {
+        Some(integers) => {
+            let bytes: Vec<u8> = integers.iter().flat_map(|i| i.to_le_bytes()).collect();
+            write_encoded(writer, &bytes, |writer| byte_array(writer, INT32))?;
+        }
+        None => {
+            // Distinct strings, with each row an index into them (-1 if masked)
+            let mut strings: HashMap<&str, i32> = HashMap::new();
+            let mut string_data = String::new();
+            let mut offsets: Vec<i32> = vec![0];
+            let mut indices: Vec<u8> = Vec::with_capacity(rows * 4);
+            for row in 0..rows {
+                let index = match value(row) {
+                    Some(v) => *strings.entry(&v.text).or_insert_with(|| {
+                        string_data.push_str(&v.text);
+                        offsets.push(string_data.len() as i32);
+                        offsets.len() as i32 - 2
+                    }),
+                    None => -1,
+                };
+                indices.extend(index.to_le_bytes());
+            }
+            let offsets: Vec<u8> = offsets.iter().flat_map(|o| o.to_le_bytes()).collect();
+            write_encoded(writer, &indices, |writer| {
+                write_map_len(writer, 5)?;
+                write_str(writer, "kind")?;
+                write_str(writer, "StringArray")?;
+                write_str(writer, "dataEncoding")?;
+                write_array_len(writer, 1)?;
+                byte_array(writer, INT32)?;
+                write_str(writer, "stringData")?;
+                write_str(writer, &string_data)?;
+                write_str(writer, "offsetEncoding")?;
+                write_array_len(writer, 1)?;
+                byte_array(writer, INT32)?;
+                write_str(writer, "offsets")?;
+                write_bin(writer, &offsets)?;
+                Ok(())
+            })?;
+        }
+    }
+
+    // Mask: 0 for a value, 1 for `.` and 2 for `?` or a missing cell
+    write_str(writer, "mask")?;
+    let mask: Vec<u8> = (0..rows)
+        .map(|row| match column.get(row).map(|v| v.kind) {
+            Some(ValueKind::Inapplicable) => 1,
+            Some(ValueKind::Unknown) | None => 2,
+            Some(_) => 0,
+        })
+        .collect();
+    if mask.iter().all(|&m| m == 0) {
+        write_nil(writer)?;
+    } else {
+        write_encoded(writer, &mask, |writer| byte_array(writer, UINT8))?;
+    }
+    Ok(())
+}
+
+/// Integer value that reads back as the same text
+fn as_i32(value: &Value) -> Option<i32> {
+    if value.kind != ValueKind::Integer {
+        return None;
+    }
+    let number: i32 = value.text.parse().ok()?;
+    (number.to_string() == value.text).then_some(number)
+}
+
+/// `{encoding: [encoding], data}` with the single encoding written by `encoding`
+fn write_encoded<W: Write>(
+    writer: &mut W,
+    data: &[u8],
+    encoding: impl FnOnce(&mut W) -> io::Result<()>,
+) -> io::Result<()> {
+    write_map_len(writer, 2)?;
+    write_str(writer, "encoding")?;
+    write_array_len(writer, 1)?;
+    encoding(writer)?;
+    write_str(writer, "data")?;
+    write_bin(writer, data)?;
+    Ok(())
+}
+
+fn byte_array<W: Write>(writer: &mut W, data_type: u64) -> io::Result<()> {
+    write_map_len(writer, 2)?;
+    write_str(writer, "kind")?;
+    write_str(writer, "ByteArray")?;
+    write_str(writer, "type")?;
+    write_uint(writer, data_type)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmpv::Value as Msg;
+
+    fn get<'a>(map: &'a Msg, key: &str) -> &'a Msg {
+        map.as_map()
+            .unwrap()
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(key))
+            .map(|(_, v)| v)
+            .unwrap()
+    }
+
+    fn decode(text: &str) -> Msg {
+        let mut bytes = Vec::new();
+        write_bcif(&Document::parse(text), &mut bytes).unwrap();
+        rmpv::decode::read_value(&mut bytes.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_columns() {
+        let file =
+            decode("data_1ABC\nloop_\n_atom_site.id\n_atom_site.label_alt_id\n1 .\n2 ?\n3 A\n");
+        let block = &get(&file, "dataBlocks")[0];
+        assert_eq!(get(block, "header").as_str(), Some("1ABC"));
+        let category = &get(block, "categories")[0];
+        assert_eq!(get(category, "name").as_str(), Some("_atom_site"));
+        assert_eq!(get(category, "rowCount").as_u64(), Some(3));
+
+        let id = &get(category, "columns")[0];
+        let data = get(id, "data");
+        assert_eq!(get(&get(data, "encoding")[0], "type").as_u64(), Some(INT32));
+        let expected: Vec<u8> = [1i32, 2, 3].iter().flat_map(|i| i.to_le_bytes()).collect();
+        assert_eq!(get(data, "data").as_slice(), Some(&expected[..]));
+        assert!(get(id, "mask").is_nil());
+
+        let alt_id = &get(category, "columns")[1];
+        let data = get(alt_id, "data");
+        let encoding = &get(data, "encoding")[0];
+        assert_eq!(get(encoding, "kind").as_str(), Some("StringArray"));
+        assert_eq!(get(encoding, "stringData").as_str(), Some("A"));
+        let expected: Vec<u8> = [-1i32, -1, 0]
+            .iter()
+            .flat_map(|i| i.to_le_bytes())
+            .collect();
+        assert_eq!(get(data, "data").as_slice(), Some(&expected[..]));
+        assert_eq!(
+            get(get(alt_id, "mask"), "data").as_slice(),
+            Some(&[1u8, 2, 0][..])
+        );
+    }
+
+    #[test]
+    fn test_strings_keep_text() {
+        let file = decode("data_x\n_a.n 007\n_a.t 'x y'\n");
+        let category = &get(&get(&file, "dataBlocks")[0], "categories")[0];
+        for (column, text) in get(category, "columns")
+            .as_array()
+            .unwrap()
+            .iter()
+            .zip(["007", "x y"])
+        {
+            let encoding = &get(get(column, "data"), "encoding")[0];
+            assert_eq!(get(encoding, "stringData").as_str(), Some(text));
+        }
+    }
+}
@@ -0,0 +1,15 @@
+//! `cif-lsp` language server for CIF files, speaking LSP over stdio
+//!
+//! Indexes the workspace folders on startup and serves document and workspace symbols.
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    match cifparse_rs::lsp::serve_stdio() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("cif-lsp: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
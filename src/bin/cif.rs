@@ -0,0 +1,278 @@
+//! `cif` command-line tool: query, extract and convert CIF files
+//!
+//! ```text
+//! cif get FILE DATANAME
+//! cif cat FILE CATEGORY [--columns a,b,...] [--format tsv|csv|cif]
+//! cif blocks [FILE]
+//! cif categories [FILE]
+//! cif convert IN(.cif|.json) OUT(.cif|.bcif|.pdb|.json)
+//! cif rename-chain FILE OLD NEW [--auth]
+//! cif renumber FILE OFFSET [--chain ID]
+//! cif rename-category FILE OLD NEW
+//! ```
+//! `FILE` may be `-` for stdin; compressed input and mmJSON are detected automatically.
+
+use cifparse_rs::bcif::write_bcif;
+use cifparse_rs::compression::read_text;
+use cifparse_rs::csv::CsvOptions;
+use cifparse_rs::refactor::{
+    apply_edits, rename_category, rename_chain, renumber_residues, ChainId, TextEdit,
+};
+use cifparse_rs::writer::{write_category, write_cif};
+use cifparse_rs::{split_data_name, Category, DataBlock, Document};
+use std::io::{self, Write};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage:
+  cif get FILE DATANAME
+  cif cat FILE CATEGORY [--columns a,b,...] [--format tsv|csv|cif]
+  cif blocks [FILE]
+  cif categories [FILE]
+  cif convert IN(.cif|.json) OUT(.cif|.bcif|.pdb|.json)
+  cif rename-chain FILE OLD NEW [--auth]
+  cif renumber FILE OFFSET [--chain ID]
+  cif rename-category FILE OLD NEW
+
+FILE may be '-' for stdin; gzip, zstd and bzip2 input and mmJSON are detected automatically.
+Edit commands print the edited file; chains are label_asym_id unless --auth is given,
+residue numbers are auth_seq_id and the like, limited to author chain ID with --chain.";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("cif: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let Some((command, rest)) = args.split_first() else {
+        return Err(USAGE.to_string());
+    };
+    let mut out = io::stdout().lock();
+    let output = match command.as_str() {
+        "get" => match rest {
+            [file, data_name] => get(&load(file)?, data_name),
+            _ => Err(USAGE.to_string()),
+        },
+        "cat" => cat(rest),
+        "blocks" => {
+            let doc = load(optional_file(rest)?)?;
+            Ok(doc.blocks.iter().map(|b| format!("{}\n", b.name)).collect())
+        }
+        "categories" => categories(&load(optional_file(rest)?)?),
+        "convert" => match rest {
+            [input, output] => convert(&load(input)?, output).map(|_| String::new()),
+            _ => Err(USAGE.to_string()),
+        },
+        "rename-chain" => {
+            let auth = rest.iter().any(|a| a == "--auth");
+            let positional: Vec<&String> = rest.iter().filter(|a| *a != "--auth").collect();
+            let [file, old, new] = positional[..] else {
+                return Err(USAGE.to_string());
+            };
+            let chain = if auth { ChainId::Auth } else { ChainId::Label };
+            edit(file, |text| rename_chain(text, chain, old, new))
+        }
+        "renumber" => renumber(rest),
+        "rename-category" => match rest {
+            [file, old, new] => edit(file, |text| rename_category(text, old, new)),
+            _ => Err(USAGE.to_string()),
+        },
+        "-h" | "--help" | "help" => Ok(format!("{}\n", USAGE)),
+        other => Err(format!("unknown command '{}'\n{}", other, USAGE)),
+    }?;
+    out.write_all(output.as_bytes()).map_err(|e| e.to_string())
+}
+
+fn optional_file(rest: &[String]) -> Result<&str, String> {
+    match rest {
+        [] => Ok("-"),
+        [file] => Ok(file),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+/// Read a file or stdin (`-`), decompressing gzip, zstd or bzip2 input.
+/// Input starting with `{` is read as mmJSON.
+fn load(path: &str) -> Result<Document, String> {
+    let text = load_text(path)?;
+    if text.trim_start().starts_with('{') {
+        return Document::from_mmjson_str(&text).map_err(|e| format!("{}: {}", path, e));
+    }
+    Ok(Document::parse(&text))
+}
+
+/// Read a file or stdin (`-`) as text, decompressing gzip, zstd or bzip2 input
+fn load_text(path: &str) -> Result<String, String> {
+    if path == "-" {
+        read_text(io::stdin().lock())
+    } else {
+        std::fs::File::open(path).and_then(read_text)
+    }
+    .map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Text of a file with the edits of `operation` applied
+fn edit<E: std::fmt::Display>(
+    path: &str,
+    operation: impl FnOnce(&str) -> Result<Vec<TextEdit>, E>,
+) -> Result<String, String> {
+    let text = load_text(path)?;
+    let edits = operation(&text).map_err(|e| e.to_string())?;
+    if edits.is_empty() {
+        return Err("nothing to change".to_string());
+    }
+    Ok(apply_edits(&text, &edits))
+}
+
+fn renumber(args: &[String]) -> Result<String, String> {
+    let mut positional: Vec<&str> = Vec::new();
+    let mut chain: Option<String> = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--chain" => chain = Some(iter.next().ok_or("--chain needs a value")?.clone()),
+            _ => positional.push(arg),
+        }
+    }
+    let [file, offset] = positional[..] else {
+        return Err(USAGE.to_string());
+    };
+    let offset: i64 = offset
+        .parse()
+        .map_err(|_| format!("invalid offset '{}'", offset))?;
+    edit(file, |text| {
+        renumber_residues(text, chain.as_deref(), offset)
+    })
+}
+
+/// Print every value of a data name, one per line
+fn get(doc: &Document, data_name: &str) -> Result<String, String> {
+    let (category_name, item) = split_data_name(data_name).unwrap_or(("", data_name));
+    let mut output = String::new();
+    for block in &doc.blocks {
+        if let Some(values) = block.category(category_name).and_then(|c| c.column(item)) {
+            for value in values {
+                output.push_str(&value.text);
+                output.push('\n');
+            }
+        }
+    }
+    if output.is_empty() {
+        return Err(format!("{} not found", data_name));
+    }
+    Ok(output)
+}
+
+fn cat(args: &[String]) -> Result<String, String> {
+    let mut positional: Vec<&str> = Vec::new();
+    let mut columns: Option<Vec<String>> = None;
+    let mut format = "tsv".to_string();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--columns" => {
+                let list = iter.next().ok_or("--columns needs a value")?;
+                columns = Some(list.split(',').map(|s| s.to_string()).collect());
+            }
+            "--format" => format = iter.next().ok_or("--format needs a value")?.clone(),
+            _ => positional.push(arg),
+        }
+    }
+    let [file, category_name] = positional[..] else {
+        return Err(USAGE.to_string());
+    };
+
+    let doc = load(file)?;
+    let mut output = String::new();
+    for block in &doc.blocks {
+        let Some(category) = block.category(category_name) else {
+            continue;
+        };
+        let category = select_columns(category, columns.as_deref())?;
+        match format.as_str() {
+            "tsv" | "csv" => {
+                let options = CsvOptions {
+                    delimiter: if format == "csv" { ',' } else { '\t' },
+                    ..Default::default()
+                };
+                let mut bytes = Vec::new();
+                category
+                    .to_csv(&mut bytes, &options)
+                    .map_err(|e| e.to_string())?;
+                output.push_str(&String::from_utf8_lossy(&bytes));
+            }
+            "cif" => write_category(&mut output, &category),
+            other => return Err(format!("unknown format '{}'", other)),
+        }
+    }
+    if output.is_empty() {
+        return Err(format!("{} not found", category_name));
+    }
+    Ok(output)
+}
+
+/// Restrict a category to the given items, in the given order
+fn select_columns(category: &Category, columns: Option<&[String]>) -> Result<Category, String> {
+    let Some(columns) = columns else {
+        return Ok(category.clone());
+    };
+    let mut selected = Category::new(category.name.clone(), category.is_loop);
+    for item in columns {
+        let values = category
+            .column(item)
+            .ok_or_else(|| format!("{} not found", category.data_name(item)))?;
+        selected.push_item(item.clone(), values.to_vec());
+    }
+    Ok(selected)
+}
+
+/// List categories of each block with their row counts
+fn categories(doc: &Document) -> Result<String, String> {
+    let mut output = String::new();
+    let list = |output: &mut String, block: &DataBlock, prefix: &str| {
+        for category in &block.categories {
+            output.push_str(&format!(
+                "{}{}\t{}\t{}\n",
+                prefix,
+                category.name,
+                category.items.len(),
+                category.row_count()
+            ));
+        }
+    };
+    for block in &doc.blocks {
+        if doc.blocks.len() > 1 {
+            output.push_str(&format!("data_{}\n", block.name));
+        }
+        list(&mut output, block, "");
+        for frame in &block.frames {
+            output.push_str(&format!("save_{}\n", frame.name));
+            list(&mut output, frame, "  ");
+        }
+    }
+    Ok(output)
+}
+
+fn convert(doc: &Document, output: &str) -> Result<(), String> {
+    let extension = output.rsplit('.').next().unwrap_or("");
+    let text = match extension {
+        "cif" => write_cif(doc),
+        "bcif" => {
+            let mut bytes = Vec::new();
+            write_bcif(doc, &mut bytes).map_err(|e| e.to_string())?;
+            return std::fs::write(output, bytes).map_err(|e| format!("{}: {}", output, e));
+        }
+        "json" => doc.to_mmjson_string(),
+        "pdb" => {
+            let block = doc.first_block().ok_or("no data block")?;
+            cifparse_rs::pdb::write_pdb(block)
+        }
+        other => return Err(format!("unknown output format '{}'", other)),
+    };
+    std::fs::write(output, text).map_err(|e| format!("{}: {}", output, e))
+}
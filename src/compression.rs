@@ -0,0 +1,177 @@
+//! Transparent decompression of CIF input
+//! Detects gzip, zstd and bzip2 from magic bytes and stream-decodes into text
+
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Compression format of an input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// Detect the format from the first bytes of the input
+    pub fn detect(bytes: &[u8]) -> Compression {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if bytes.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Read all of `reader` as UTF-8 text, decompressing it if needed.
+/// The whole decompressed text is held in memory; `Document::from_reader` streams instead.
+pub fn read_text<R: Read>(reader: R) -> io::Result<String> {
+    String::from_utf8(read_bytes(reader, None)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Read all of `reader`, decompressing it if needed.
+/// Stops one byte past `max_bytes` of output, so a longer result means the limit was exceeded.
+pub fn read_bytes<R: Read>(reader: R, max_bytes: Option<usize>) -> io::Result<Vec<u8>> {
+    let limit = max_bytes.map_or(u64::MAX, |max| max as u64 + 1);
+    let mut bytes = Vec::new();
+    decompress(reader)?.take(limit).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Reader of the decompressed contents of `reader`, decoded as it is read,
+/// or of `reader` itself if it is not compressed
+pub fn decompress<'a, R: Read + 'a>(reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    let mut reader = BufReader::new(reader);
+    Ok(match Compression::detect(reader.fill_buf()?) {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(BufReader::new(decode_gzip(reader)?)),
+        Compression::Zstd => Box::new(BufReader::new(decode_zstd(reader)?)),
+        Compression::Bzip2 => Box::new(BufReader::new(decode_bzip2(reader)?)),
+    })
+}
+
+#[cfg(feature = "gzip")]
+fn decode_gzip<'a, R: BufRead + 'a>(reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader)))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn decode_gzip<'a, R: BufRead + 'a>(_reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    Err(unsupported("gzip"))
+}
+
+#[cfg(feature = "zstd")]
+fn decode_zstd<'a, R: BufRead + 'a>(reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(Box::new(ZstdFrames {
+        reader: Some(reader),
+        frame: None,
+    }))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decode_zstd<'a, R: BufRead + 'a>(_reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    Err(unsupported("zstd"))
+}
+
+/// Decoder of concatenated zstd frames, as a file may hold several
+#[cfg(feature = "zstd")]
+struct ZstdFrames<R: BufRead> {
+    /// Input between frames
+    reader: Option<R>,
+    /// Frame being decoded
+    frame: Option<ruzstd::decoding::StreamingDecoder<R, ruzstd::decoding::FrameDecoder>>,
+}
+
+#[cfg(feature = "zstd")]
+impl<R: BufRead> Read for ZstdFrames<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(frame) = self.frame.as_mut() {
+                let n = frame.read(buf)?;
+                if n > 0 || buf.is_empty() {
+                    return Ok(n);
+                }
+                self.reader = self.frame.take().map(|frame| frame.into_inner());
+            }
+            let Some(mut reader) = self.reader.take() else {
+                return Ok(0);
+            };
+            if reader.fill_buf()?.is_empty() {
+                return Ok(0);
+            }
+            let frame = ruzstd::decoding::StreamingDecoder::new(reader)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.frame = Some(frame);
+        }
+    }
+}
+
+#[cfg(feature = "bzip2")]
+fn decode_bzip2<'a, R: BufRead + 'a>(reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(Box::new(bzip2::bufread::MultiBzDecoder::new(reader)))
+}
+
+#[cfg(not(feature = "bzip2"))]
+fn decode_bzip2<'a, R: BufRead + 'a>(_reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    Err(unsupported("bzip2"))
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd", feature = "bzip2")))]
+fn unsupported(format: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} input requires the `{}` feature", format, format),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CIF: &str = "data_x\n_entry.id 1ABC\n";
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Compression::detect(b"data_x"), Compression::None);
+        assert_eq!(Compression::detect(&[0x1f, 0x8b, 8]), Compression::Gzip);
+        assert_eq!(Compression::detect(b"BZh9"), Compression::Bzip2);
+    }
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(read_text(CIF.as_bytes()).unwrap(), CIF);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip() {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(CIF.as_bytes()).unwrap();
+        let bytes = encoder.finish().unwrap();
+        assert_eq!(read_text(bytes.as_slice()).unwrap(), CIF);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+        let mut bytes = compress_to_vec(CIF.as_bytes(), CompressionLevel::Fastest);
+        bytes.extend(compress_to_vec(CIF.as_bytes(), CompressionLevel::Fastest));
+        assert_eq!(read_text(bytes.as_slice()).unwrap(), CIF.repeat(2));
+    }
+
+    #[cfg(feature = "bzip2")]
+    #[test]
+    fn test_bzip2() {
+        let encoder = bzip2::read::BzEncoder::new(CIF.as_bytes(), bzip2::Compression::fast());
+        let mut bytes = Vec::new();
+        BufReader::new(encoder).read_to_end(&mut bytes).unwrap();
+        assert_eq!(read_text(bytes.as_slice()).unwrap(), CIF);
+    }
+}
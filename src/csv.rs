@@ -0,0 +1,243 @@
+//! CSV/TSV export and import of single categories
+
+use crate::{Category, Value, ValueKind};
+use std::io::{self, Read, Write};
+
+/// Options for CSV/TSV export and import
+#[derive(Clone, Debug)]
+pub struct CsvOptions {
+    /// Field delimiter (`,` for CSV, `\t` for TSV)
+    pub delimiter: char,
+    /// Write or expect a header row of item names
+    pub header: bool,
+    /// Cell text for `?`
+    pub unknown: String,
+    /// Cell text for `.`
+    pub inapplicable: String,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            header: true,
+            unknown: "?".to_string(),
+            inapplicable: ".".to_string(),
+        }
+    }
+}
+
+impl CsvOptions {
+    /// Tab-separated output with the default null tokens
+    pub fn tsv() -> Self {
+        CsvOptions {
+            delimiter: '\t',
+            ..Default::default()
+        }
+    }
+}
+
+impl Category {
+    /// Write the category as CSV/TSV, one row per loop row
+    pub fn to_csv<W: Write>(&self, mut writer: W, options: &CsvOptions) -> io::Result<()> {
+        if options.header {
+            let names: Vec<String> = self
+                .items
+                .iter()
+                .map(|item| quote_field(item, options.delimiter))
+                .collect();
+            write_record(&mut writer, &names, options.delimiter)?;
+        }
+        for row in 0..self.row_count() {
+            let cells: Vec<String> = self
+                .row(row)
+                .into_iter()
+                .map(|value| {
+                    let text = match value.map(|v| (v.kind, v.text.as_str())) {
+                        None | Some((ValueKind::Unknown, _)) => options.unknown.as_str(),
+                        Some((ValueKind::Inapplicable, _)) => options.inapplicable.as_str(),
+                        Some((_, text)) => text,
+                    };
+                    quote_field(text, options.delimiter)
+                })
+                .collect();
+            write_record(&mut writer, &cells, options.delimiter)?;
+        }
+        Ok(())
+    }
+
+    /// Read a CSV/TSV table with a header row of item names into a new loop category
+    pub fn from_csv<R: Read>(
+        name: impl Into<String>,
+        mut reader: R,
+        options: &CsvOptions,
+    ) -> io::Result<Category> {
+        if !options.header {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "CSV import needs a header row of item names",
+            ));
+        }
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let mut records = parse_records(&text, options.delimiter).into_iter();
+
+        let mut category = Category::new(name, true);
+        for item in records.next().unwrap_or_default() {
+            category.push_item(item.trim(), Vec::new());
+        }
+        for (line, record) in records.enumerate() {
+            if record.len() != category.items.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "row {} has {} fields, expected {}",
+                        line + 1,
+                        record.len(),
+                        category.items.len()
+                    ),
+                ));
+            }
+            for (column, cell) in category.columns.iter_mut().zip(record) {
+                let value = if cell == options.unknown {
+                    Value::unknown()
+                } else if cell == options.inapplicable {
+                    Value {
+                        text: ".".to_string(),
+                        kind: ValueKind::Inapplicable,
+                    }
+                } else {
+                    Value::from_text(cell)
+                };
+                column.push(value);
+            }
+        }
+        Ok(category)
+    }
+}
+
+fn write_record<W: Write>(writer: &mut W, cells: &[String], delimiter: char) -> io::Result<()> {
+    let mut line = cells.join(delimiter.encode_utf8(&mut [0; 4]));
+    line.push('\n');
+    writer.write_all(line.as_bytes())
+}
+
+/// Quote a field if it contains the delimiter, quotes, line breaks or edge spaces
+fn quote_field(text: &str, delimiter: char) -> String {
+    let needs_quotes = text.contains(delimiter)
+        || text.contains(['"', '\n', '\r'])
+        || text.starts_with(' ')
+        || text.ends_with(' ');
+    if needs_quotes {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Split CSV text into records, handling quoted fields with `""` escapes and line breaks
+fn parse_records(text: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut records: Vec<Vec<String>> = Vec::new();
+    let mut record: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                field.push(c);
+            }
+        } else if c == '"' && field.is_empty() {
+            in_quotes = true;
+        } else if c == delimiter {
+            record.push(std::mem::take(&mut field));
+        } else if c == '\n' || c == '\r' {
+            if c == '\r' && chars.peek() == Some(&'\n') {
+                chars.next();
+            }
+            record.push(std::mem::take(&mut field));
+            records.push(std::mem::take(&mut record));
+        } else {
+            field.push(c);
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    // Skip blank lines
+    records.retain(|r| !(r.len() == 1 && r[0].is_empty()));
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Document;
+
+    const CIF: &str = r#"data_x
+loop_
+_refine_ls_shell.d_res_high
+_refine_ls_shell.number_reflns_obs
+_refine_ls_shell.pdbx_refine_id
+_refine_ls_shell.details
+2.50 1200 'X-RAY DIFFRACTION' ?
+2.10 .    'X-RAY DIFFRACTION' 'a, "b"'
+"#;
+
+    #[test]
+    fn test_to_csv() {
+        let doc = Document::parse(CIF);
+        let category = doc.blocks[0].category("_refine_ls_shell").unwrap();
+        let mut out = Vec::new();
+        let options = CsvOptions {
+            unknown: String::new(),
+            ..Default::default()
+        };
+        category.to_csv(&mut out, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "d_res_high,number_reflns_obs,pdbx_refine_id,details\n\
+             2.50,1200,X-RAY DIFFRACTION,\n\
+             2.10,.,X-RAY DIFFRACTION,\"a, \"\"b\"\"\"\n"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut doc = Document::parse(CIF);
+        let category = doc.blocks[0].category("_refine_ls_shell").unwrap().clone();
+
+        let mut tsv = Vec::new();
+        category.to_csv(&mut tsv, &CsvOptions::tsv()).unwrap();
+        let imported =
+            Category::from_csv("_refine_ls_shell", tsv.as_slice(), &CsvOptions::tsv()).unwrap();
+        assert_eq!(imported, category);
+
+        // Edited table written back into the CIF
+        let mut edited = imported;
+        edited.columns[1][1] = Value::new("950");
+        doc.blocks[0].set_category(edited);
+        let written = crate::writer::write_cif(&doc);
+        let reread = Document::parse(&written);
+        let shell = reread.blocks[0].category("_refine_ls_shell").unwrap();
+        assert_eq!(
+            shell.value("number_reflns_obs", 1).unwrap().as_i64(),
+            Some(950)
+        );
+    }
+
+    #[test]
+    fn test_ragged_rows() {
+        let result = Category::from_csv("_a", "x,y\n1\n".as_bytes(), &CsvOptions::default());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,799 @@
+//! Diagnostics for common CIF syntax errors, with quick fixes as text edits
+//! Fixes quote split values, wrap long lines, pad loop rows and repair headers and line endings;
+//! duplicate blocks, items and categories are reported; `Document::merge_duplicates` merges them
+
+use crate::parser::parse_cif_internal;
+use crate::refactor::{utf16_edits, TextEdit};
+use crate::semantic::utf16_column;
+use crate::writer::format_value;
+use crate::{
+    classify_value, Document, HighlightOptions, Item, LoopBlock, ParseResult, Token, TokenKind,
+    Value, ValueKind,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use tsify::Tsify;
+
+/// Longest line allowed by CIF 1.1, in characters
+pub const MAX_LINE_LENGTH: usize = 2048;
+
+/// How serious a diagnostic is
+#[derive(Serialize, Tsify, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+}
+
+/// Kind of problem found
+#[derive(Serialize, Tsify, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticCode {
+    /// Value not bound to a data name, e.g. the second word of an unquoted title
+    ValueWithoutName,
+    /// Line longer than `MAX_LINE_LENGTH`
+    LineTooLong,
+    /// Last loop row with fewer values than the loop has data names
+    ShortLoopRow,
+    /// Data name with its value appended without whitespace (`_entry.id'1ABC'`)
+    GluedValue,
+    /// Data before the first `data_` header
+    MissingDataHeader,
+    /// `\r\n` line endings
+    CrlfLineEndings,
+    /// Data block or save frame name used twice
+    DuplicateBlock,
+    /// Data name defined twice in a block
+    DuplicateItem,
+    /// Category defined both as a loop and as key-value pairs, or in two loops
+    SplitCategory,
+}
+
+impl DiagnosticCode {
+    /// Code as reported to editors, e.g. `short_loop_row`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DiagnosticCode::ValueWithoutName => "value_without_name",
+            DiagnosticCode::LineTooLong => "line_too_long",
+            DiagnosticCode::ShortLoopRow => "short_loop_row",
+            DiagnosticCode::GluedValue => "glued_value",
+            DiagnosticCode::MissingDataHeader => "missing_data_header",
+            DiagnosticCode::CrlfLineEndings => "crlf_line_endings",
+            DiagnosticCode::DuplicateBlock => "duplicate_block",
+            DiagnosticCode::DuplicateItem => "duplicate_item",
+            DiagnosticCode::SplitCategory => "split_category",
+        }
+    }
+}
+
+/// Quick fix of a diagnostic
+#[derive(Serialize, Tsify, Clone, Debug, PartialEq, Eq)]
+pub struct Fix {
+    /// Action title, e.g. `Quote 'My protein'`
+    pub title: String,
+    pub edits: Vec<TextEdit>,
+}
+
+/// Another location involved in a diagnostic, e.g. the first definition of a duplicate
+#[derive(Serialize, Tsify, Clone, Debug, PartialEq, Eq)]
+pub struct Related {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub message: String,
+}
+
+/// A problem at `start..end` (byte columns) of a line
+#[derive(Serialize, Tsify, Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub severity: Severity,
+    pub code: DiagnosticCode,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<Fix>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub related: Vec<Related>,
+}
+
+/// Diagnostics of CIF text in line order
+pub fn diagnostics(text: &str) -> Vec<Diagnostic> {
+    diagnostics_with(
+        text,
+        &parse_cif_internal(text, &HighlightOptions::default()),
+    )
+}
+
+/// `diagnostics` on an existing parse result of `text`
+pub(crate) fn diagnostics_with(text: &str, result: &ParseResult) -> Vec<Diagnostic> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut found = Vec::new();
+    values_without_name(result, &lines, &mut found);
+    long_lines(result, &lines, &mut found);
+    short_loop_rows(result, &lines, &mut found);
+    glued_values(result, &mut found);
+    missing_header(text, result, &lines, &mut found);
+    crlf_line_endings(text, &mut found);
+    duplicates(result, &lines, &mut found);
+    found.sort_by_key(|d| (d.line, d.start));
+    found
+}
+
+/// Diagnostics with positions and edits in UTF-16 columns of the lines given by `line_text`
+pub(crate) fn utf16_diagnostics<'t>(
+    diagnostics: Vec<Diagnostic>,
+    line_text: &dyn Fn(usize) -> &'t str,
+) -> Vec<Diagnostic> {
+    let related = |related: Related| Related {
+        start: utf16_column(line_text(related.line), related.start),
+        end: utf16_column(line_text(related.line), related.end),
+        ..related
+    };
+    diagnostics
+        .into_iter()
+        .map(|d| Diagnostic {
+            start: utf16_column(line_text(d.line), d.start),
+            end: utf16_column(line_text(d.line), d.end),
+            fix: d.fix.map(|fix| Fix {
+                edits: utf16_edits(fix.edits, line_text),
+                ..fix
+            }),
+            related: d.related.into_iter().map(related).collect(),
+            ..d
+        })
+        .collect()
+}
+
+fn is_value(token: &Token) -> bool {
+    token.value_kind.is_some() && token.kind != TokenKind::TextField
+}
+
+/// Values after a key-value's value on the same line; quoting the run is the usual fix
+fn values_without_name(result: &ParseResult, lines: &[&str], found: &mut Vec<Diagnostic>) {
+    let tokens = &result.tokens;
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        if !is_value(token) || token.item_name.is_some() {
+            i += 1;
+            continue;
+        }
+        // Run of unbound values on this line
+        let first = i;
+        while i < tokens.len()
+            && tokens[i].line == token.line
+            && is_value(&tokens[i])
+            && tokens[i].item_name.is_none()
+        {
+            i += 1;
+        }
+        let last = &tokens[i - 1];
+        let bound = first
+            .checked_sub(1)
+            .map(|b| &tokens[b])
+            .filter(|b| b.line == token.line && is_value(b) && b.item_name.is_some());
+        let line = lines.get(token.line).copied().unwrap_or("");
+        let fix = bound.and_then(|bound| {
+            let run = &tokens[first - 1..i];
+            if run.iter().any(|t| {
+                t.value_kind != Some(ValueKind::Bare)
+                    && !t.value_kind.is_some_and(|k| k.is_number() || k.is_null())
+            }) {
+                return None;
+            }
+            let words = line.get(bound.start..last.start + last.length)?;
+            let quoted = format_value(&Value {
+                text: words.to_string(),
+                kind: ValueKind::SingleQuoted,
+            });
+            (!quoted.starts_with(';')).then(|| Fix {
+                title: format!("Quote {}", quoted),
+                edits: vec![TextEdit {
+                    line: token.line,
+                    start: bound.start,
+                    end_line: token.line,
+                    end: last.start + last.length,
+                    new_text: quoted,
+                }],
+            })
+        });
+        let message = match bound.and_then(|b| b.item_name.as_deref()) {
+            Some(name) => format!(
+                "value without a data name; is the value of {} missing quotes?",
+                name
+            ),
+            None => "value without a data name".to_string(),
+        };
+        found.push(Diagnostic {
+            line: token.line,
+            start: token.start,
+            end: last.start + last.length,
+            severity: Severity::Error,
+            code: DiagnosticCode::ValueWithoutName,
+            message,
+            fix,
+            related: Vec::new(),
+        });
+    }
+}
+
+/// Lines over the CIF limit; the longest quoted value on the line moves to a text field,
+/// wrapped at whitespace if it is still too long
+fn long_lines(result: &ParseResult, lines: &[&str], found: &mut Vec<Diagnostic>) {
+    for (number, line) in lines.iter().enumerate() {
+        let length = line.chars().count();
+        if length <= MAX_LINE_LENGTH {
+            continue;
+        }
+        // Unterminated quotes run to the end of the line and are left alone
+        let closed = |t: &&Token| {
+            let raw = line.get(t.start..t.start + t.length).unwrap_or("");
+            raw.len() >= 2 && raw.ends_with(&raw[..1])
+        };
+        let longest = result
+            .tokens
+            .iter()
+            .skip_while(|t| t.line < number)
+            .take_while(|t| t.line == number)
+            .filter(|t| {
+                matches!(
+                    t.value_kind,
+                    Some(ValueKind::SingleQuoted | ValueKind::DoubleQuoted)
+                )
+            })
+            .filter(closed)
+            .max_by_key(|t| t.length);
+        let fix = longest.and_then(|t| {
+            let content = line.get(t.start + 1..t.start + t.length - 1)?;
+            // Text fields start at the beginning of a line; the rest of the line follows them
+            let start = line[..t.start].trim_end().len();
+            let rest = &line[t.start + t.length..];
+            let mut new_text = format!("\n;{}\n;", wrap_text_field(content).join("\n"));
+            if !rest.trim().is_empty() {
+                new_text.push('\n');
+            }
+            Some(Fix {
+                title: "Convert to a text field".to_string(),
+                edits: vec![TextEdit {
+                    line: number,
+                    end_line: number,
+                    start,
+                    end: t.start + t.length,
+                    new_text,
+                }],
+            })
+        });
+        found.push(Diagnostic {
+            line: number,
+            start: 0,
+            end: line.len(),
+            severity: Severity::Warning,
+            code: DiagnosticCode::LineTooLong,
+            message: format!(
+                "line of {} characters exceeds the CIF limit of {}",
+                length, MAX_LINE_LENGTH
+            ),
+            fix,
+            related: Vec::new(),
+        });
+    }
+}
+
+/// `content` split into text field lines of at most `MAX_LINE_LENGTH` characters with the
+/// leading `;`, breaking at a space or tab that the break replaces. Words too long for a
+/// line are split. No line but the first starts with `;`, which would end the field.
+fn wrap_text_field(content: &str) -> Vec<&str> {
+    let limit = MAX_LINE_LENGTH - 1;
+    let mut lines = Vec::new();
+    let mut rest = content;
+    while rest.chars().count() > limit {
+        let space = rest
+            .char_indices()
+            .take(limit + 1)
+            .filter(|&(i, c)| i > 0 && matches!(c, ' ' | '\t') && !rest[i + 1..].starts_with(';'))
+            .last();
+        if let Some((i, _)) = space {
+            lines.push(&rest[..i]);
+            rest = &rest[i + 1..];
+            continue;
+        }
+        let mut cut = rest
+            .char_indices()
+            .nth(limit)
+            .map_or(rest.len(), |(i, _)| i);
+        while rest[cut..].starts_with(';') {
+            cut = rest[..cut].char_indices().last().map_or(0, |(i, _)| i);
+        }
+        if cut == 0 {
+            break;
+        }
+        lines.push(&rest[..cut]);
+        rest = &rest[cut..];
+    }
+    lines.push(rest);
+    lines
+}
+
+/// Loops whose value count is not a multiple of their data names; pads the last row with `?`
+fn short_loop_rows(result: &ParseResult, lines: &[&str], found: &mut Vec<Diagnostic>) {
+    for block in &result.loops {
+        let width = block.items.len();
+        let count = block.processed_value_count;
+        if !block.is_in_loop_block || width == 0 || count % width == 0 {
+            continue;
+        }
+        let Some(data_line) = block.data_lines.last() else {
+            continue;
+        };
+        let Some(range) = data_line.value_ranges.last() else {
+            continue;
+        };
+        let missing = width - count % width;
+        let padding = vec!["?"; missing].join(" ");
+        let end = range.start + range.length;
+        // Text after the closing `;` of a text field is ignored, so pad on a new line
+        let (insert_at, new_text) = if range.kind == ValueKind::TextField {
+            let line = lines.get(data_line.line).copied().unwrap_or("");
+            (line.len(), format!("\n{}", padding))
+        } else {
+            (end, format!(" {}", padding))
+        };
+        found.push(Diagnostic {
+            line: data_line.line,
+            start: range.start,
+            end,
+            severity: Severity::Error,
+            code: DiagnosticCode::ShortLoopRow,
+            message: format!(
+                "last row of {} has {} of {} values",
+                block.category_name,
+                width - missing,
+                width
+            ),
+            fix: Some(Fix {
+                title: format!("Pad the row with {}", padding),
+                edits: vec![TextEdit {
+                    line: data_line.line,
+                    start: insert_at,
+                    end_line: data_line.line,
+                    end: insert_at,
+                    new_text,
+                }],
+            }),
+            related: Vec::new(),
+        });
+    }
+}
+
+/// Data names with a quoted value or a number appended (`_entry.id'1ABC'`,
+/// `_cell.length_a12.345`); a space splits them
+fn glued_values(result: &ParseResult, found: &mut Vec<Diagnostic>) {
+    for block in &result.loops {
+        for item in &block.items {
+            let Some(split) = glued_split(&item.name) else {
+                continue;
+            };
+            let name_start = item.start - block.category_name.len() - 1;
+            let column = item.start + split;
+            found.push(Diagnostic {
+                line: item.line,
+                start: name_start,
+                end: item.start + item.length,
+                severity: Severity::Error,
+                code: DiagnosticCode::GluedValue,
+                message: format!(
+                    "data name {}.{} runs into its value {}",
+                    block.category_name,
+                    &item.name[..split],
+                    &item.name[split..]
+                ),
+                fix: Some(Fix {
+                    title: "Separate the data name and value".to_string(),
+                    edits: vec![TextEdit {
+                        line: item.line,
+                        start: column,
+                        end_line: item.line,
+                        end: column,
+                        new_text: " ".to_string(),
+                    }],
+                }),
+                related: Vec::new(),
+            });
+        }
+    }
+}
+
+/// Offset in an item name where an appended value starts: at a quote, or at a number
+/// containing a second dot of the data name
+fn glued_split(item: &str) -> Option<usize> {
+    if let Some(quote) = item.find(['\'', '"']).filter(|&q| q > 0) {
+        return Some(quote);
+    }
+    if !item.contains('.') {
+        return None;
+    }
+    (1..item.len())
+        .filter(|&i| item.is_char_boundary(i))
+        .find(|&i| item[i..].contains('.') && classify_value(&item[i..], false).is_number())
+}
+
+/// Data before the first block header; inserts `data_<_entry.id>` above it
+fn missing_header(text: &str, result: &ParseResult, lines: &[&str], found: &mut Vec<Diagnostic>) {
+    let Some(first) = result.tokens.iter().find(|t| t.kind != TokenKind::Comment) else {
+        return;
+    };
+    if first.kind == TokenKind::BlockHeader {
+        return;
+    }
+    let name = Document::parse(text)
+        .blocks
+        .first()
+        .and_then(|b| b.value("_entry.id"))
+        .and_then(|v| v.as_str())
+        .map(|id| id.replace(char::is_whitespace, "_"))
+        .unwrap_or_else(|| "untitled".to_string());
+    let line = lines.get(first.line).copied().unwrap_or("");
+    found.push(Diagnostic {
+        line: first.line,
+        start: first.start,
+        end: line.len(),
+        severity: Severity::Error,
+        code: DiagnosticCode::MissingDataHeader,
+        message: "data before the first data_ block header".to_string(),
+        fix: Some(Fix {
+            title: format!("Add data_{} header", name),
+            edits: vec![TextEdit {
+                line: first.line,
+                start: 0,
+                end_line: first.line,
+                end: 0,
+                new_text: format!("data_{}\n", name),
+            }],
+        }),
+        related: Vec::new(),
+    });
+}
+
+/// `\r\n` line endings, reported once at the first; the fix replaces each with `\n`
+fn crlf_line_endings(text: &str, found: &mut Vec<Diagnostic>) {
+    let crlf: Vec<(usize, usize)> = text
+        .split_inclusive('\n')
+        .enumerate()
+        .filter_map(|(number, line)| {
+            let content = line.strip_suffix("\r\n")?;
+            Some((number, content.len()))
+        })
+        .collect();
+    let Some(&(line, length)) = crlf.first() else {
+        return;
+    };
+    found.push(Diagnostic {
+        line,
+        start: length,
+        end: length,
+        severity: Severity::Information,
+        code: DiagnosticCode::CrlfLineEndings,
+        message: format!("{} lines end with \\r\\n", crlf.len()),
+        fix: Some(Fix {
+            title: "Convert line endings to \\n".to_string(),
+            edits: crlf
+                .into_iter()
+                .map(|(line, length)| TextEdit {
+                    line,
+                    start: length,
+                    end_line: line + 1,
+                    end: 0,
+                    new_text: "\n".to_string(),
+                })
+                .collect(),
+        }),
+        related: Vec::new(),
+    });
+}
+
+/// Position of a header or data name: line, start and end column
+type Span = (usize, usize, usize);
+
+/// Duplicate block and frame names, duplicate data names and split categories,
+/// each pointing at the later definition with the first one as related location
+fn duplicates(result: &ParseResult, lines: &[&str], found: &mut Vec<Diagnostic>) {
+    let line_text = |line: usize| lines.get(line).copied().unwrap_or("");
+    let mut duplicate = |code, at: Span, first: Span, message: String| {
+        found.push(Diagnostic {
+            line: at.0,
+            start: at.1,
+            end: at.2,
+            severity: Severity::Error,
+            code,
+            message,
+            fix: None,
+            related: vec![Related {
+                line: first.0,
+                start: first.1,
+                end: first.2,
+                message: "first defined here".to_string(),
+            }],
+        });
+    };
+
+    // Scopes of data names: a data block, or a save frame within it
+    let mut scopes: Vec<((usize, usize), usize)> = Vec::new();
+    let mut block_scope = 0;
+    let mut block_names: HashMap<String, Span> = HashMap::new();
+    let mut frame_names: HashMap<String, Span> = HashMap::new();
+    for token in result
+        .tokens
+        .iter()
+        .filter(|t| t.kind == TokenKind::BlockHeader)
+    {
+        let header = &line_text(token.line)[token.start..token.start + token.length];
+        let span = (token.line, token.start, token.start + token.length);
+        let lower = header.to_ascii_lowercase();
+        let scope = scopes.len() + 1;
+        if let Some(name) = lower.strip_prefix("data_") {
+            block_scope = scope;
+            frame_names.clear();
+            scopes.push(((token.line, token.start), scope));
+            if let Some(&first) = block_names.get(name) {
+                duplicate(
+                    DiagnosticCode::DuplicateBlock,
+                    span,
+                    first,
+                    format!("duplicate data block {}", header),
+                );
+            }
+            block_names.entry(name.to_string()).or_insert(span);
+        } else if let Some(name) = lower.strip_prefix("save_") {
+            if name.is_empty() {
+                // End of a save frame
+                scopes.push(((token.line, token.start), block_scope));
+                continue;
+            }
+            scopes.push(((token.line, token.start), scope));
+            if let Some(&first) = frame_names.get(name) {
+                duplicate(
+                    DiagnosticCode::DuplicateBlock,
+                    span,
+                    first,
+                    format!("duplicate save frame {}", header),
+                );
+            }
+            frame_names.entry(name.to_string()).or_insert(span);
+        }
+    }
+    let scope_of = |item: &Item| {
+        let index = scopes.partition_point(|&(at, _)| at <= (item.line, item.start));
+        index.checked_sub(1).map_or(0, |i| scopes[i].1)
+    };
+    let name_span = |block: &LoopBlock, item: &Item| {
+        let start = item.start - block.category_name.len() - 1;
+        (item.line, start, item.start + item.length)
+    };
+
+    let mut categories: HashMap<(usize, String), &LoopBlock> = HashMap::new();
+    let mut data_names: HashMap<(usize, String), Span> = HashMap::new();
+    for block in &result.loops {
+        let Some(first_item) = block.items.first() else {
+            continue;
+        };
+        let scope = scope_of(first_item);
+        let category = block.category_name.to_ascii_lowercase();
+        match categories.get(&(scope, category.clone())) {
+            Some(&earlier) if earlier.is_in_loop_block || block.is_in_loop_block => {
+                let message = if earlier.is_in_loop_block && block.is_in_loop_block {
+                    format!("category {} is defined in two loops", block.category_name)
+                } else {
+                    format!(
+                        "category {} is defined both as a loop and as key-value pairs",
+                        block.category_name
+                    )
+                };
+                duplicate(
+                    DiagnosticCode::SplitCategory,
+                    name_span(block, first_item),
+                    name_span(earlier, &earlier.items[0]),
+                    message,
+                );
+            }
+            Some(_) => {}
+            None => {
+                categories.insert((scope, category.clone()), block);
+            }
+        }
+        for item in &block.items {
+            let span = name_span(block, item);
+            let key = (
+                scope,
+                format!("{}.{}", category, item.name.to_ascii_lowercase()),
+            );
+            match data_names.get(&key) {
+                Some(&first) => duplicate(
+                    DiagnosticCode::DuplicateItem,
+                    span,
+                    first,
+                    format!("duplicate data name {}.{}", block.category_name, item.name),
+                ),
+                None => {
+                    data_names.insert(key, span);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::refactor::apply_edits;
+    use crate::semantic::line_of;
+
+    fn fixed(text: &str, code: DiagnosticCode) -> String {
+        let found = diagnostics(text);
+        let diagnostic = found.iter().find(|d| d.code == code).unwrap();
+        apply_edits(text, &diagnostic.fix.as_ref().unwrap().edits)
+    }
+
+    #[test]
+    fn test_clean_file() {
+        let text = "data_1ABC\n#\n_entry.id 1ABC\n#\n_struct.title\n;Crystal structure of\nsomething\n;\n\
+_struct.pdbx_descriptor 'A \"quoted\" name'\n#\nloop_\n_citation_author.citation_id\n\
+_citation_author.name\n_citation_author.ordinal\nprimary 'Smith, J.' 1\nprimary\n;Doe, J.\n;\n2\n#\n\
+loop_\n_atom_site.group_PDB\n_atom_site.id\n_atom_site.label_alt_id\n_atom_site.Cartn_x\n\
+ATOM 1 . 1.234(5)\nHETATM 2 A -0.5 # ligand\n#\n";
+        assert_eq!(diagnostics(text), []);
+    }
+
+    #[test]
+    fn test_value_without_name() {
+        let text = "data_x\n_struct.title My protein 2 # comment\n_struct.id 1\n";
+        let found = diagnostics(text);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].line, found[0].start, found[0].end), (1, 17, 26));
+        assert_eq!(
+            fixed(text, DiagnosticCode::ValueWithoutName),
+            "data_x\n_struct.title 'My protein 2' # comment\n_struct.id 1\n"
+        );
+        // A stray value on its own line has no fix
+        let found = diagnostics("data_x\n_a.b 1\nstray\n");
+        assert_eq!(found[0].code, DiagnosticCode::ValueWithoutName);
+        assert!(found[0].fix.is_none());
+    }
+
+    #[test]
+    fn test_long_line() {
+        let long = "x ".repeat(MAX_LINE_LENGTH / 2);
+        let text = format!("data_x\n_a.b '{}' _a.c 1\n", long.trim_end());
+        let result = fixed(&text, DiagnosticCode::LineTooLong);
+        assert_eq!(
+            result,
+            format!("data_x\n_a.b\n;{}\n;\n _a.c 1\n", long.trim_end())
+        );
+        assert!(diagnostics(&result).is_empty());
+
+        // Several times the limit: wrapped at spaces, no line too long
+        let words: Vec<String> = (0..2000).map(|i| format!("w{}", i)).collect();
+        let long = words.join(" ");
+        let text = format!("data_x\n_a.b '{}'\n", long);
+        let result = fixed(&text, DiagnosticCode::LineTooLong);
+        assert!(result.lines().count() > 5);
+        assert!(result.lines().all(|l| l.chars().count() <= MAX_LINE_LENGTH));
+        assert!(diagnostics(&result).is_empty());
+        let doc = Document::parse(&result);
+        let value = doc.blocks[0].value("_a.b").unwrap();
+        assert_eq!(value.text.replace('\n', " "), long);
+
+        // A word longer than a line is split; no line after the first starts with `;`
+        let word = format!(
+            "{};{}",
+            "x".repeat(MAX_LINE_LENGTH - 1),
+            "y".repeat(MAX_LINE_LENGTH)
+        );
+        let lines = wrap_text_field(&word);
+        assert_eq!(lines.concat(), word);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1..].iter().all(|l| !l.starts_with(';')));
+
+        // Unterminated quotes have no fix
+        let text = format!("data_x\n_a.b {} '\n", "x".repeat(MAX_LINE_LENGTH));
+        let found = diagnostics(&text);
+        assert_eq!(found[0].code, DiagnosticCode::LineTooLong);
+        assert!(found[0].fix.is_none());
+        let text = format!("data_x\n_a.b '{}é\n", "x".repeat(MAX_LINE_LENGTH));
+        let found = diagnostics(&text);
+        assert_eq!(found[0].code, DiagnosticCode::LineTooLong);
+        assert!(found[0].fix.is_none());
+    }
+
+    #[test]
+    fn test_short_loop_row() {
+        let text = "data_x\nloop_\n_a.x\n_a.y\n_a.z\n1 2 3\n4\n_b.c 1\n";
+        assert_eq!(
+            fixed(text, DiagnosticCode::ShortLoopRow),
+            "data_x\nloop_\n_a.x\n_a.y\n_a.z\n1 2 3\n4 ? ?\n_b.c 1\n"
+        );
+        let text = "data_x\nloop_\n_a.x\n_a.y\n_a.z\n1\n;\ntext\n;\n";
+        let result = fixed(text, DiagnosticCode::ShortLoopRow);
+        assert_eq!(
+            result,
+            "data_x\nloop_\n_a.x\n_a.y\n_a.z\n1\n;\ntext\n;\n?\n"
+        );
+        assert!(diagnostics(&result).is_empty());
+    }
+
+    #[test]
+    fn test_glued_value() {
+        let text = "data_x\n_entry.id'1ABC'\n_cell.length_a12.345\n_cell.angle_alpha 90\n";
+        let found = diagnostics(text);
+        let glued: Vec<&Diagnostic> = found
+            .iter()
+            .filter(|d| d.code == DiagnosticCode::GluedValue)
+            .collect();
+        assert_eq!(glued.len(), 2);
+        let edits: Vec<TextEdit> = glued
+            .iter()
+            .flat_map(|d| d.fix.clone().unwrap().edits)
+            .collect();
+        let result = apply_edits(text, &edits);
+        assert_eq!(
+            result,
+            "data_x\n_entry.id '1ABC'\n_cell.length_a 12.345\n_cell.angle_alpha 90\n"
+        );
+        assert!(diagnostics(&result).is_empty());
+    }
+
+    #[test]
+    fn test_missing_header() {
+        let text = "#\\#CIF_2.0\n_entry.id '1 ABC'\n";
+        assert_eq!(
+            fixed(text, DiagnosticCode::MissingDataHeader),
+            "#\\#CIF_2.0\ndata_1_ABC\n_entry.id '1 ABC'\n"
+        );
+        assert!(diagnostics("# only a comment\n").is_empty());
+    }
+
+    #[test]
+    fn test_crlf() {
+        let text = "data_x\r\n_a.b 'é'\r\n_a.c 1\n";
+        let found = diagnostics(text);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].message, "2 lines end with \\r\\n");
+        assert_eq!(
+            fixed(text, DiagnosticCode::CrlfLineEndings),
+            "data_x\n_a.b 'é'\n_a.c 1\n"
+        );
+        let lines: Vec<&str> = text.lines().collect();
+        let utf16 = utf16_diagnostics(found, &line_of(&lines));
+        let edit = &utf16[0].fix.as_ref().unwrap().edits[1];
+        assert_eq!(
+            (edit.line, edit.start, edit.end_line, edit.end),
+            (1, 8, 2, 0)
+        );
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let text = "data_a\n_x.id 1\nloop_\n_x.name\nfoo\nbar\n_y.v 1\n_y.V 2\n\
+save_f\n_z.id 1\nsave_\nsave_F\n_z.id 2\nsave_\n\
+data_b\n_x.id 1\ndata_A\n";
+        let found: Vec<(DiagnosticCode, usize, usize, usize)> = diagnostics(text)
+            .iter()
+            .map(|d| (d.code, d.line, d.start, d.related[0].line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (DiagnosticCode::SplitCategory, 3, 0, 1),
+                (DiagnosticCode::DuplicateItem, 7, 0, 6),
+                (DiagnosticCode::DuplicateBlock, 11, 0, 8),
+                (DiagnosticCode::DuplicateBlock, 16, 0, 0),
+            ]
+        );
+        assert_eq!(
+            diagnostics(text)[0].message,
+            "category _x is defined both as a loop and as key-value pairs"
+        );
+    }
+}
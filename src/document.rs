@@ -0,0 +1,706 @@
+//! Document model for CIF data
+//! Data blocks, save frames, categories and typed values built from the token stream
+
+use crate::compression::decompress;
+use crate::tokenizer::{
+    classify_value, is_block_keyword, is_data_name, is_loop_keyword, split_data_name, tokenize,
+    unquote, Lexeme, LexemeKind, RawLexeme, RawLexemes,
+};
+use crate::writer::needs_quotes;
+use crate::ValueKind;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+
+/// Bytes of input tokenized at once by `Document::from_reader`
+const READ_CHUNK: usize = 64 * 1024;
+
+/// A single CIF value with its lexical kind
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Value {
+    /// Value text without quotes or text field delimiters
+    pub text: String,
+    pub kind: ValueKind,
+}
+
+impl Value {
+    /// Create a value, classifying unquoted text
+    pub fn new(text: impl Into<String>) -> Self {
+        let text = text.into();
+        let kind = classify_value(&text, false);
+        Value { text, kind }
+    }
+
+    /// Create a value from raw text (e.g. a JSON or CSV cell),
+    /// choosing the quoting it needs to be written back as the same text
+    pub fn from_text(text: impl Into<String>) -> Self {
+        let text = text.into();
+        let kind = match classify_value(&text, false) {
+            // Text that looks like a null is a real value
+            ValueKind::Unknown | ValueKind::Inapplicable => ValueKind::SingleQuoted,
+            ValueKind::Bare if text.contains(['\n', '\r']) => ValueKind::TextField,
+            ValueKind::Bare if needs_quotes(&text, ValueKind::Bare) => ValueKind::SingleQuoted,
+            kind => kind,
+        };
+        Value { text, kind }
+    }
+
+    /// Value of a lexeme with its raw text (quotes included)
+    pub(crate) fn from_lexeme(kind: LexemeKind, raw: &str) -> Self {
+        let value_kind = match kind {
+            LexemeKind::TextField => ValueKind::TextField,
+            _ => classify_value(raw, kind == LexemeKind::Quoted),
+        };
+        Value {
+            text: unquote(kind, raw).to_string(),
+            kind: value_kind,
+        }
+    }
+
+    /// The `?` (unknown) value
+    pub fn unknown() -> Self {
+        Value {
+            text: "?".to_string(),
+            kind: ValueKind::Unknown,
+        }
+    }
+
+    /// Whether the value is `?` or `.`
+    pub fn is_null(&self) -> bool {
+        self.kind.is_null()
+    }
+
+    /// Text of the value, or `None` for `?` and `.`
+    pub fn as_str(&self) -> Option<&str> {
+        (!self.is_null()).then_some(self.text.as_str())
+    }
+
+    /// Numeric value, ignoring any standard uncertainty
+    pub fn as_f64(&self) -> Option<f64> {
+        if !self.kind.is_number() {
+            return None;
+        }
+        let number = self.text.split('(').next().unwrap_or(&self.text);
+        number.parse().ok()
+    }
+
+    /// Integer value
+    pub fn as_i64(&self) -> Option<i64> {
+        match self.kind {
+            ValueKind::Integer => self.text.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// A category: key-value pairs or a loop, stored column by column.
+/// `V` is [`Value`] except in a [`LazyDocument`](crate::lazy::LazyDocument), which keeps byte offsets.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Category<V = Value> {
+    /// Category name with leading underscore (e.g. `_atom_site`);
+    /// empty for data names without a dot
+    pub name: String,
+    /// Item names without the category prefix (e.g. `id`)
+    pub items: Vec<String>,
+    /// One column of values per item
+    pub columns: Vec<Vec<V>>,
+    /// Defined with `loop_`
+    pub is_loop: bool,
+}
+
+impl<V> Category<V> {
+    /// Create an empty category
+    pub fn new(name: impl Into<String>, is_loop: bool) -> Self {
+        Category {
+            name: name.into(),
+            items: Vec::new(),
+            columns: Vec::new(),
+            is_loop,
+        }
+    }
+
+    /// Number of rows
+    pub fn row_count(&self) -> usize {
+        self.columns.iter().map(|c| c.len()).max().unwrap_or(0)
+    }
+
+    /// Index of an item
+    pub fn item_index(&self, item: &str) -> Option<usize> {
+        self.items.iter().position(|i| i == item)
+    }
+
+    /// All values of an item
+    pub fn column(&self, item: &str) -> Option<&[V]> {
+        self.item_index(item).map(|i| self.columns[i].as_slice())
+    }
+
+    /// Value of an item in a row
+    pub fn value(&self, item: &str, row: usize) -> Option<&V> {
+        self.column(item)?.get(row)
+    }
+
+    /// Values of a row in item order
+    pub fn row(&self, row: usize) -> Vec<Option<&V>> {
+        self.columns.iter().map(|c| c.get(row)).collect()
+    }
+
+    /// Full data name of an item (`_category.item`)
+    pub fn data_name(&self, item: &str) -> String {
+        if self.name.is_empty() {
+            item.to_string()
+        } else {
+            format!("{}.{}", self.name, item)
+        }
+    }
+
+    /// Add an item with its values
+    pub fn push_item(&mut self, item: impl Into<String>, values: Vec<V>) {
+        self.items.push(item.into());
+        self.columns.push(values);
+    }
+}
+
+/// A data block (`data_`) or save frame (`save_`)
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct DataBlock<V = Value> {
+    /// Block name without the `data_` / `save_` prefix
+    pub name: String,
+    pub categories: Vec<Category<V>>,
+    /// Save frames nested in the block
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub frames: Vec<DataBlock<V>>,
+}
+
+impl<V> Default for DataBlock<V> {
+    fn default() -> Self {
+        DataBlock {
+            name: String::new(),
+            categories: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+}
+
+impl<V> DataBlock<V> {
+    /// Create an empty block
+    pub fn new(name: impl Into<String>) -> Self {
+        DataBlock {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Find a category by name (`_atom_site`)
+    pub fn category(&self, name: &str) -> Option<&Category<V>> {
+        self.categories.iter().find(|c| c.name == name)
+    }
+
+    /// Find a category by name for editing
+    pub fn category_mut(&mut self, name: &str) -> Option<&mut Category<V>> {
+        self.categories.iter_mut().find(|c| c.name == name)
+    }
+
+    /// Replace the category with the same name, or append it
+    pub fn set_category(&mut self, category: Category<V>) {
+        match self.category_mut(&category.name) {
+            Some(existing) => *existing = category,
+            None => self.categories.push(category),
+        }
+    }
+
+    /// First value of a data name (`_entry.id`)
+    pub fn value(&self, data_name: &str) -> Option<&V> {
+        let (category, item) = split_data_name(data_name).unwrap_or(("", data_name));
+        self.category(category)?.value(item, 0)
+    }
+}
+
+/// A parsed CIF document
+#[derive(Serialize, Clone, Debug, PartialEq, Default)]
+pub struct Document {
+    pub blocks: Vec<DataBlock>,
+}
+
+impl Document {
+    /// Parse CIF text into a document
+    pub fn parse(text: &str) -> Document {
+        Document {
+            blocks: build_blocks(tokenize(text).iter().map(Lexeme::raw)),
+        }
+    }
+
+    /// Parse CIF from a reader, decompressing gzip, zstd or bzip2 input.
+    /// Input is decoded and tokenized in chunks, so only the model is kept in memory.
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<Document> {
+        let mut reader = decompress(reader)?;
+        let mut builder = Builder::new();
+        let mut chunk = Vec::new();
+        let (mut base, mut line) = (0, 0);
+        let mut chunk_lines = 0;
+        let mut in_text_field = false;
+        loop {
+            let line_start = chunk.len();
+            let read = reader.read_until(b'\n', &mut chunk)?;
+            if read > 0 {
+                chunk_lines += 1;
+                in_text_field ^= chunk[line_start] == b';';
+            }
+            // Text fields span lines, so a chunk only ends outside one
+            if read == 0 || (!in_text_field && chunk.len() >= READ_CHUNK) {
+                std::str::from_utf8(&chunk)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                for lexeme in RawLexemes::new(&chunk, base, line) {
+                    builder.feed(&lexeme);
+                }
+                base += chunk.len();
+                line += chunk_lines;
+                chunk_lines = 0;
+                chunk.clear();
+            }
+            if read == 0 {
+                return Ok(Document {
+                    blocks: builder.finish(),
+                });
+            }
+        }
+    }
+
+    /// Parse CIF bytes, decompressing gzip, zstd or bzip2 input
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Document> {
+        Document::from_reader(bytes)
+    }
+
+    /// Open and parse a CIF file, detecting compression from its magic bytes
+    pub fn from_path(path: impl AsRef<Path>) -> io::Result<Document> {
+        Document::from_reader(File::open(path)?)
+    }
+
+    /// Find a data block by name
+    pub fn block(&self, name: &str) -> Option<&DataBlock> {
+        self.blocks.iter().find(|b| b.name == name)
+    }
+
+    /// First data block
+    pub fn first_block(&self) -> Option<&DataBlock> {
+        self.blocks.first()
+    }
+
+    /// Resolve duplicates: blocks with the same name are joined, then each block's duplicate
+    /// frames, categories and data names are merged (see [`DataBlock::merge_duplicates`]).
+    /// Names are compared ignoring ASCII case. Returns the number of merges.
+    pub fn merge_duplicates(&mut self) -> usize {
+        let (blocks, mut merged) = merge_blocks(std::mem::take(&mut self.blocks));
+        self.blocks = blocks;
+        for block in &mut self.blocks {
+            merged += block.merge_duplicates();
+        }
+        merged
+    }
+}
+
+impl DataBlock {
+    /// Merge save frames with the same name and categories defined more than once.
+    /// Key-value parts of a category are joined into one row, a later value of a data name
+    /// replacing an earlier one. When a loop is involved, parts with different items and
+    /// the same row count are joined column by column; otherwise rows are appended, with `?`
+    /// for items a part lacks. Returns the number of merges.
+    pub fn merge_duplicates(&mut self) -> usize {
+        let (frames, mut merged) = merge_blocks(std::mem::take(&mut self.frames));
+        self.frames = frames;
+        for frame in &mut self.frames {
+            merged += frame.merge_duplicates();
+        }
+        let mut categories: Vec<Category> = Vec::new();
+        for category in std::mem::take(&mut self.categories) {
+            let category = dedup_items(category, &mut merged);
+            match categories
+                .iter_mut()
+                .find(|c| c.name.eq_ignore_ascii_case(&category.name))
+            {
+                Some(existing) => {
+                    existing.merge(category);
+                    merged += 1;
+                }
+                None => categories.push(category),
+            }
+        }
+        self.categories = categories;
+        merged
+    }
+}
+
+impl Category {
+    /// Merge a later part of the same category into this one
+    fn merge(&mut self, other: Category) {
+        let index =
+            |items: &[String], item: &str| items.iter().position(|i| i.eq_ignore_ascii_case(item));
+        let disjoint = other.items.iter().all(|i| index(&self.items, i).is_none());
+        let rows = self.row_count();
+        if !self.is_loop && !other.is_loop {
+            // Key-value pairs: one row, later values win
+            for (item, column) in other.items.into_iter().zip(other.columns) {
+                match index(&self.items, &item) {
+                    Some(i) => self.columns[i] = column,
+                    None => self.push_item(item, column),
+                }
+            }
+        } else if disjoint && rows == other.row_count() {
+            for (item, column) in other.items.into_iter().zip(other.columns) {
+                self.push_item(item, column);
+            }
+            self.is_loop = true;
+        } else {
+            let other_rows = other.row_count();
+            for column in &mut self.columns {
+                column.resize(rows, Value::unknown());
+            }
+            for (item, column) in other.items.into_iter().zip(other.columns) {
+                let i = match index(&self.items, &item) {
+                    Some(i) => i,
+                    None => {
+                        self.push_item(item, vec![Value::unknown(); rows]);
+                        self.items.len() - 1
+                    }
+                };
+                self.columns[i].extend(column);
+            }
+            for column in &mut self.columns {
+                column.resize(rows + other_rows, Value::unknown());
+            }
+            self.is_loop = true;
+        }
+    }
+}
+
+/// Merge data names repeated within one category: the later value of a key-value item wins,
+/// the first column of a looped item is kept
+fn dedup_items(category: Category, merged: &mut usize) -> Category {
+    let mut deduped = Category::new(category.name, category.is_loop);
+    for (item, column) in category.items.into_iter().zip(category.columns) {
+        match deduped
+            .items
+            .iter()
+            .position(|i| i.eq_ignore_ascii_case(&item))
+        {
+            Some(i) => {
+                if !deduped.is_loop {
+                    deduped.columns[i] = column;
+                }
+                *merged += 1;
+            }
+            None => deduped.push_item(item, column),
+        }
+    }
+    deduped
+}
+
+/// Join blocks or frames with the same name into the first of them
+fn merge_blocks(blocks: Vec<DataBlock>) -> (Vec<DataBlock>, usize) {
+    let mut merged = 0;
+    let mut unique: Vec<DataBlock> = Vec::new();
+    for block in blocks {
+        match unique
+            .iter_mut()
+            .find(|b| b.name.eq_ignore_ascii_case(&block.name))
+        {
+            Some(existing) => {
+                existing.categories.extend(block.categories);
+                existing.frames.extend(block.frames);
+                merged += 1;
+            }
+            None => unique.push(block),
+        }
+    }
+    (unique, merged)
+}
+
+/// Representation of a value while building categories
+pub trait Cell {
+    fn from_lexeme(lexeme: &RawLexeme) -> Self;
+}
+
+impl Cell for Value {
+    fn from_lexeme(lexeme: &RawLexeme) -> Self {
+        Value::from_lexeme(lexeme.kind, &String::from_utf8_lossy(lexeme.text))
+    }
+}
+
+/// Byte offset of the value in the source, decoded on demand
+impl Cell for u32 {
+    fn from_lexeme(lexeme: &RawLexeme) -> Self {
+        lexeme.offset as u32
+    }
+}
+
+/// Byte offset of the value in a source of any size
+impl Cell for u64 {
+    fn from_lexeme(lexeme: &RawLexeme) -> Self {
+        lexeme.offset as u64
+    }
+}
+
+/// Build data blocks holding `V` for each value from a lexeme stream.
+/// Block and data names are decoded lossily; values are left to `V`.
+pub(crate) fn build_blocks<'a, V: Cell>(
+    lexemes: impl IntoIterator<Item = RawLexeme<'a>>,
+) -> Vec<DataBlock<V>> {
+    let mut builder = Builder::new();
+    for lexeme in lexemes {
+        builder.feed(&lexeme);
+    }
+    builder.finish()
+}
+
+/// Builds data blocks while walking the token stream
+struct Builder<V> {
+    blocks: Vec<DataBlock<V>>,
+    /// Save frame being filled inside the last block
+    frame: Option<DataBlock<V>>,
+    /// Category being filled
+    category: Option<Category<V>>,
+    /// Loop header still collecting item names
+    in_loop_header: bool,
+    /// Values consumed by the current loop
+    loop_values: usize,
+    /// Key-value item waiting for its value
+    pending_item: Option<usize>,
+}
+
+impl<V: Cell> Builder<V> {
+    fn new() -> Self {
+        Builder {
+            blocks: Vec::new(),
+            frame: None,
+            category: None,
+            in_loop_header: false,
+            loop_values: 0,
+            pending_item: None,
+        }
+    }
+
+    fn feed(&mut self, lexeme: &RawLexeme) {
+        match lexeme.kind {
+            LexemeKind::Comment => {}
+            LexemeKind::Quoted | LexemeKind::TextField => self.value(lexeme),
+            LexemeKind::Word => {
+                if is_block_keyword(lexeme.text, false) {
+                    self.block_keyword(&String::from_utf8_lossy(lexeme.text));
+                } else if is_loop_keyword(lexeme.text, false) {
+                    self.finish_category();
+                    self.category = Some(Category::new("", true));
+                    self.in_loop_header = true;
+                } else if is_data_name(lexeme.text, false) {
+                    self.data_name(&String::from_utf8_lossy(lexeme.text));
+                } else {
+                    self.value(lexeme);
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<DataBlock<V>> {
+        self.finish_frame();
+        self.blocks
+    }
+
+    fn block_keyword(&mut self, keyword: &str) {
+        if let Some(name) = keyword.strip_prefix("save_") {
+            self.finish_frame();
+            if !name.is_empty() {
+                self.frame = Some(DataBlock::new(name));
+            }
+        } else {
+            self.finish_frame();
+            let name = keyword.strip_prefix("data_").unwrap_or(keyword);
+            self.blocks.push(DataBlock::new(name));
+        }
+    }
+
+    /// Block or save frame receiving categories
+    fn target(&mut self) -> &mut DataBlock<V> {
+        if let Some(frame) = self.frame.as_mut() {
+            return frame;
+        }
+        if self.blocks.is_empty() {
+            // Data before any data_ header
+            self.blocks.push(DataBlock::new(""));
+        }
+        self.blocks.last_mut().unwrap()
+    }
+
+    fn finish_category(&mut self) {
+        self.pending_item = None;
+        self.in_loop_header = false;
+        self.loop_values = 0;
+        if let Some(category) = self.category.take() {
+            if !category.items.is_empty() {
+                self.target().categories.push(category);
+            }
+        }
+    }
+
+    fn finish_frame(&mut self) {
+        self.finish_category();
+        if let Some(frame) = self.frame.take() {
+            self.target().frames.push(frame);
+        }
+    }
+
+    fn data_name(&mut self, data_name: &str) {
+        let (category_name, item) = match split_data_name(data_name) {
+            Some((category, item)) => (category.to_string(), item.to_string()),
+            None => (String::new(), data_name.to_string()),
+        };
+
+        let continues = match self.category.as_mut() {
+            Some(category) if self.in_loop_header => {
+                if category.items.is_empty() {
+                    category.name = category_name.clone();
+                }
+                category.name == category_name
+            }
+            Some(category) => !category.is_loop && category.name == category_name,
+            None => false,
+        };
+        if !continues {
+            let is_loop = self.in_loop_header;
+            self.finish_category();
+            self.in_loop_header = is_loop;
+            self.category = Some(Category::new(category_name, is_loop));
+        }
+
+        let category = self.category.as_mut().unwrap();
+        category.push_item(item, Vec::new());
+        if !category.is_loop {
+            self.pending_item = Some(category.items.len() - 1);
+        }
+    }
+
+    fn value(&mut self, lexeme: &RawLexeme) {
+        let Some(category) = self.category.as_mut() else {
+            return;
+        };
+        if category.is_loop {
+            if category.items.is_empty() {
+                return;
+            }
+            self.in_loop_header = false;
+            let column = self.loop_values % category.items.len();
+            category.columns[column].push(V::from_lexeme(lexeme));
+            self.loop_values += 1;
+        } else if let Some(column) = self.pending_item.take() {
+            category.columns[column].push(V::from_lexeme(lexeme));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::diagnostics;
+    use crate::writer::write_cif;
+
+    const CIF: &str = r#"data_1ABC
+_entry.id 1ABC
+_struct.title
+;Crystal structure
+of a protein
+;
+loop_
+_atom_site.id
+_atom_site.type_symbol
+_atom_site.Cartn_x
+1 N 1.5
+2 C ?
+save_frame
+_item.name '_atom_site.id'
+save_
+data_second
+_cell_length_a 10.0(2)
+"#;
+
+    #[test]
+    fn test_document_structure() {
+        let doc = Document::parse(CIF);
+        assert_eq!(doc.blocks.len(), 2);
+
+        let block = doc.block("1ABC").unwrap();
+        assert_eq!(block.value("_entry.id").unwrap().text, "1ABC");
+        assert_eq!(
+            block.value("_struct.title").unwrap().text,
+            "Crystal structure\nof a protein"
+        );
+
+        let atom_site = block.category("_atom_site").unwrap();
+        assert!(atom_site.is_loop);
+        assert_eq!(atom_site.row_count(), 2);
+        assert_eq!(atom_site.value("type_symbol", 1).unwrap().text, "C");
+        assert_eq!(atom_site.value("Cartn_x", 0).unwrap().as_f64(), Some(1.5));
+        assert!(atom_site.value("Cartn_x", 1).unwrap().is_null());
+
+        assert_eq!(block.frames.len(), 1);
+        let item = block.frames[0].value("_item.name").unwrap();
+        assert_eq!(item.text, "_atom_site.id");
+        assert_eq!(item.kind, ValueKind::SingleQuoted);
+
+        let second = doc.block("second").unwrap();
+        let cell = second.value("_cell_length_a").unwrap();
+        assert_eq!(cell.kind, ValueKind::FloatWithSu);
+        assert_eq!(cell.as_f64(), Some(10.0));
+    }
+
+    #[test]
+    fn test_value_after_text_field() {
+        let doc = Document::parse("data_x\nloop_\n_a.x\n_a.y\n;t\n; A\n2 B\n");
+        let a = doc.blocks[0].category("_a").unwrap();
+        assert_eq!(a.column("x").unwrap()[0].text, "t");
+        assert_eq!(a.column("y").unwrap()[0].text, "A");
+        assert_eq!(a.value("y", 1).unwrap().text, "B");
+    }
+
+    #[test]
+    fn test_merge_duplicates() {
+        let text = "data_a\n_x.id 1\n_y.v 1\n_x.name foo\n_y.v 2\n\
+loop_\n_z.id\n1\n2\nloop_\n_z.id\n_z.name\n3 c\n\
+loop_\n_w.a\n1\n2\nloop_\n_w.b\nx\ny\ndata_A\n_v.id 1\n";
+        let mut doc = Document::parse(text);
+        assert_eq!(doc.merge_duplicates(), 5);
+        assert_eq!(doc.blocks.len(), 1);
+        let block = &doc.blocks[0];
+        let names: Vec<&str> = block.categories.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["_x", "_y", "_z", "_w", "_v"]);
+
+        let x = block.category("_x").unwrap();
+        assert_eq!((x.is_loop, x.items.len()), (false, 2));
+        assert_eq!(block.value("_y.v").unwrap().text, "2");
+
+        let z = block.category("_z").unwrap();
+        assert_eq!(z.items, ["id", "name"]);
+        assert_eq!(z.column("id").unwrap().len(), 3);
+        assert!(z.value("name", 0).unwrap().is_null());
+        assert_eq!(z.value("name", 2).unwrap().text, "c");
+
+        let w = block.category("_w").unwrap();
+        assert_eq!(
+            (w.row_count(), w.value("b", 1).unwrap().text.as_str()),
+            (2, "y")
+        );
+
+        assert!(diagnostics(&write_cif(&doc)).is_empty());
+    }
+
+    #[test]
+    fn test_from_reader_chunks() {
+        let mut text = String::from("data_a\nloop_\n_x.id\n_x.note\n");
+        for i in 0..READ_CHUNK / 16 {
+            text.push_str(&format!("{} 'é {}'\n", i, i));
+        }
+        text.push_str(";\nfirst\n");
+        text.push_str(&"long line\n".repeat(READ_CHUNK / 8));
+        text.push_str("; last\n_y.id 1");
+        let doc = Document::from_reader(text.as_bytes()).unwrap();
+        assert_eq!(doc, Document::parse(&text));
+        assert!(Document::from_bytes(b"data_a\n_x.id \xff\n").is_err());
+    }
+}
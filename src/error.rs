@@ -0,0 +1,158 @@
+//! Limits and errors of guarded parsing
+//! Errors are raised when input is not UTF-8, cannot be decompressed or exceeds `ParseLimits`
+
+use serde::Deserialize;
+use std::fmt;
+use std::io;
+use std::string::FromUtf8Error;
+use tsify::Tsify;
+
+/// Limits for parsing untrusted or very large input; `None` means unlimited
+#[derive(Deserialize, Tsify, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct ParseLimits {
+    /// Largest accepted input in bytes, after decompression
+    pub max_input_bytes: Option<usize>,
+    /// Most highlighting tokens produced before parsing is aborted
+    pub max_tokens: Option<usize>,
+}
+
+impl ParseLimits {
+    /// Fail if `size` bytes of input exceed `max_input_bytes`
+    pub fn check_size(&self, size: usize) -> Result<(), ParseError> {
+        match self.max_input_bytes {
+            Some(limit) if size > limit => Err(ParseError::InputTooLarge { size, limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Error while reading or parsing CIF input under `ParseLimits`
+#[derive(Debug)]
+pub enum ParseError {
+    /// Input bytes are not valid UTF-8
+    InvalidUtf8(FromUtf8Error),
+    /// Compressed input could not be decoded
+    Decompress(io::Error),
+    /// Input is larger than `ParseLimits::max_input_bytes`. Compressed input is
+    /// only inflated to `limit + 1` bytes, which is then the reported `size`.
+    InputTooLarge { size: usize, limit: usize },
+    /// Parsing was aborted after more than `ParseLimits::max_tokens` tokens
+    LimitExceeded { limit: usize },
+}
+
+impl ParseError {
+    /// Name of the JavaScript error class, e.g. `InputTooLargeError`
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParseError::InvalidUtf8(_) => "InvalidUtf8Error",
+            ParseError::Decompress(_) => "DecompressError",
+            ParseError::InputTooLarge { .. } => "InputTooLargeError",
+            ParseError::LimitExceeded { .. } => "LimitExceededError",
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidUtf8(e) => write!(f, "input is not valid UTF-8: {}", e),
+            ParseError::Decompress(e) => write!(f, "cannot decompress input: {}", e),
+            ParseError::InputTooLarge { size, limit } => {
+                write!(
+                    f,
+                    "input of {} bytes exceeds the limit of {} bytes",
+                    size, limit
+                )
+            }
+            ParseError::LimitExceeded { limit } => {
+                write!(f, "parsing aborted after {} tokens", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::InvalidUtf8(e) => Some(e),
+            ParseError::Decompress(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{decode_bytes, parse_with_limits, HighlightOptions, ParseError, ParseLimits};
+
+    const CIF: &str = "data_x\nloop_\n_a.x\n1\n2\n3\n";
+
+    #[test]
+    fn test_limits() {
+        let options = HighlightOptions::default();
+        let unlimited = ParseLimits::default();
+        assert_eq!(
+            parse_with_limits(CIF, &options, &unlimited)
+                .unwrap()
+                .tokens
+                .len(),
+            7
+        );
+
+        let small = ParseLimits {
+            max_input_bytes: Some(10),
+            ..Default::default()
+        };
+        let error = parse_with_limits(CIF, &options, &small).unwrap_err();
+        assert!(matches!(
+            error,
+            ParseError::InputTooLarge {
+                size: 24,
+                limit: 10
+            }
+        ));
+        assert_eq!(error.name(), "InputTooLargeError");
+
+        let few_tokens = ParseLimits {
+            max_tokens: Some(6),
+            ..Default::default()
+        };
+        let error = parse_with_limits(CIF, &options, &few_tokens).unwrap_err();
+        assert!(matches!(error, ParseError::LimitExceeded { limit: 6 }));
+    }
+
+    #[test]
+    fn test_decode_bytes() {
+        let limits = ParseLimits::default();
+        assert_eq!(decode_bytes(CIF.as_bytes(), &limits).unwrap(), CIF);
+        let error = decode_bytes(b"data_\xff", &limits).unwrap_err();
+        assert_eq!(error.name(), "InvalidUtf8Error");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_decompression_limit() {
+        use std::io::Write;
+        // 16 MiB of spaces compresses to under 128 KiB
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        for _ in 0..16 {
+            encoder.write_all(&[b' '; 1 << 20]).unwrap();
+        }
+        let bytes = encoder.finish().unwrap();
+        assert!(bytes.len() < 1 << 17);
+
+        let limits = ParseLimits {
+            max_input_bytes: Some(1000),
+            ..Default::default()
+        };
+        let error = decode_bytes(&bytes, &limits).unwrap_err();
+        assert!(matches!(
+            error,
+            ParseError::InputTooLarge {
+                size: 1001,
+                limit: 1000
+            }
+        ));
+    }
+}
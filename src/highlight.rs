@@ -0,0 +1,364 @@
+//! Structure-aware highlighting for `_atom_site` rows
+//! Recolors whole rows by chain, residue, entity, group or alternate location
+
+use crate::{LoopBlock, ParseResult};
+use serde::Deserialize;
+use std::collections::HashMap;
+use tsify::Tsify;
+
+pub(crate) const ATOM_SITE: &str = "_atom_site";
+
+/// How value tokens of `_atom_site` rows are colored
+#[derive(Deserialize, Tsify, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HighlightMode {
+    /// Rotate colors by column (the default rainbow)
+    #[default]
+    Column,
+    /// One color per `label_asym_id`
+    Chain,
+    /// Alternate two colors whenever the residue changes
+    Residue,
+    /// One color per `label_entity_id`
+    Entity,
+    /// One color per `group_PDB` value (ATOM vs HETATM)
+    #[serde(rename = "group_PDB", alias = "group_pdb")]
+    GroupPdb,
+    /// One color per `label_alt_id`, with `.` and `?` sharing the first color
+    AltId,
+}
+
+impl HighlightMode {
+    /// Parse a mode name as used by the WASM API
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "column" => Some(HighlightMode::Column),
+            "chain" => Some(HighlightMode::Chain),
+            "residue" => Some(HighlightMode::Residue),
+            "entity" => Some(HighlightMode::Entity),
+            "group_PDB" | "group_pdb" => Some(HighlightMode::GroupPdb),
+            "alt_id" => Some(HighlightMode::AltId),
+            _ => None,
+        }
+    }
+
+    /// Item names that identify the group a row belongs to
+    fn key_items(self) -> &'static [&'static str] {
+        match self {
+            HighlightMode::Column => &[],
+            HighlightMode::Chain => &["label_asym_id"],
+            HighlightMode::Residue => &[
+                "label_asym_id",
+                "label_seq_id",
+                "auth_seq_id",
+                "pdbx_PDB_ins_code",
+                "label_comp_id",
+            ],
+            HighlightMode::Entity => &["label_entity_id"],
+            HighlightMode::GroupPdb => &["group_PDB"],
+            HighlightMode::AltId => &["label_alt_id"],
+        }
+    }
+}
+
+/// Highlighting options
+#[derive(Deserialize, Tsify, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct HighlightOptions {
+    /// Number of colors item names, values and text fields rotate through
+    pub rotation_length: u8,
+    /// Emit `Null`, `Number` and `QuotedString` instead of `Value`
+    pub distinguish_value_kinds: bool,
+    pub mode: HighlightMode,
+    /// Mark tokens of rows with `occupancy` < 1 as dimmed
+    pub dim_partial_occupancy: bool,
+}
+
+impl Default for HighlightOptions {
+    fn default() -> Self {
+        HighlightOptions {
+            rotation_length: 7,
+            distinguish_value_kinds: false,
+            mode: HighlightMode::Column,
+            dim_partial_occupancy: false,
+        }
+    }
+}
+
+/// A value of an `_atom_site` row, located by line and start column
+struct RowValue {
+    line: usize,
+    start: usize,
+    length: usize,
+    column_index: usize,
+}
+
+/// Group numbering of the rows of an `_atom_site` loop so far,
+/// from which coloring can continue at a later row
+#[derive(Clone, Debug, Default)]
+pub(crate) struct GroupState {
+    group_index: HashMap<String, usize>,
+    last_key: Option<String>,
+    residue_toggle: usize,
+}
+
+impl GroupState {
+    /// Group index of the next row, whose key values joined are `key`
+    fn next(&mut self, mode: HighlightMode, key: String) -> usize {
+        match mode {
+            HighlightMode::Residue => {
+                if self.last_key.as_ref().is_some_and(|k| *k != key) {
+                    self.residue_toggle ^= 1;
+                }
+                self.last_key = Some(key);
+                self.residue_toggle
+            }
+            HighlightMode::AltId if key == "." || key == "?" => 0,
+            HighlightMode::AltId => {
+                let next = self.group_index.len() + 1;
+                *self.group_index.entry(key).or_insert(next)
+            }
+            _ => {
+                let next = self.group_index.len();
+                *self.group_index.entry(key).or_insert(next)
+            }
+        }
+    }
+
+    /// Number the rows of `block`, whose values lie on `lines` from `first_line` on
+    pub(crate) fn advance(
+        &mut self,
+        block: &LoopBlock,
+        lines: &[&str],
+        first_line: usize,
+        mode: HighlightMode,
+    ) {
+        let key_columns = key_columns(block, mode);
+        if key_columns.is_empty() {
+            return;
+        }
+        for row in split_rows(block) {
+            self.next(mode, row_key(&row, &key_columns, lines, first_line));
+        }
+    }
+}
+
+/// Whether `block` is an `_atom_site` loop, the only block that is recolored
+pub(crate) fn is_atom_site_loop(block: &LoopBlock) -> bool {
+    block.category_name == ATOM_SITE && block.is_in_loop_block && !block.items.is_empty()
+}
+
+/// Recolor `_atom_site` value tokens according to `options`
+pub fn apply_highlight(result: &mut ParseResult, text: &str, options: &HighlightOptions) {
+    apply_highlight_from(result, text, 0, options, None);
+}
+
+/// Recolor `_atom_site` value tokens of a result parsed from `text`,
+/// the source from line `first_line` on. Rows of a loop that started before
+/// `first_line` continue the numbering of `resume`.
+pub(crate) fn apply_highlight_from(
+    result: &mut ParseResult,
+    text: &str,
+    first_line: usize,
+    options: &HighlightOptions,
+    resume: Option<&GroupState>,
+) {
+    if options.mode == HighlightMode::Column && !options.dim_partial_occupancy {
+        return;
+    }
+
+    let lines: Vec<&str> = text.lines().collect();
+    // (line, start) -> (rotation override, dimmed)
+    let mut overrides: HashMap<(usize, usize), (Option<u8>, bool)> = HashMap::new();
+
+    for block in &result.loops {
+        if !is_atom_site_loop(block) {
+            continue;
+        }
+
+        let key_columns = key_columns(block, options.mode);
+        let occupancy_column = block.items.iter().position(|item| item.name == "occupancy");
+
+        let mut groups = match resume {
+            Some(groups) if block.start_line < first_line => groups.clone(),
+            _ => GroupState::default(),
+        };
+
+        for row in split_rows(block) {
+            let color = if key_columns.is_empty() {
+                None
+            } else {
+                let key = row_key(&row, &key_columns, &lines, first_line);
+                let index = groups.next(options.mode, key);
+                Some((index % options.rotation_length.max(1) as usize) as u8)
+            };
+
+            let dimmed = options.dim_partial_occupancy
+                && occupancy_column
+                    .and_then(|col| value_of(&row, col, &lines, first_line))
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .is_some_and(|occ| occ < 1.0);
+
+            for v in &row {
+                overrides.insert((v.line, v.start), (color, dimmed));
+            }
+        }
+    }
+
+    if overrides.is_empty() {
+        return;
+    }
+
+    for token in &mut result.tokens {
+        let is_atom_site_value = token
+            .item_name
+            .as_deref()
+            .is_some_and(|name| name.starts_with("_atom_site."));
+        if !is_atom_site_value {
+            continue;
+        }
+        if let Some(&(color, dimmed)) = overrides.get(&(token.line, token.start)) {
+            if let Some(rotation) = color {
+                token.rotation = rotation;
+            }
+            token.dimmed = dimmed;
+        }
+    }
+}
+
+/// Columns of `block` holding the key items of `mode`
+fn key_columns(block: &LoopBlock, mode: HighlightMode) -> Vec<usize> {
+    mode.key_items()
+        .iter()
+        .filter_map(|name| block.items.iter().position(|item| item.name == *name))
+        .collect()
+}
+
+/// Text of the value of `row` in column `col`
+fn value_of<'a>(
+    row: &[RowValue],
+    col: usize,
+    lines: &[&'a str],
+    first_line: usize,
+) -> Option<&'a str> {
+    row.iter().find(|v| v.column_index == col).and_then(|v| {
+        lines
+            .get(v.line - first_line)?
+            .get(v.start..v.start + v.length)
+    })
+}
+
+/// Key values of `row` joined into one string
+fn row_key(row: &[RowValue], key_columns: &[usize], lines: &[&str], first_line: usize) -> String {
+    let key: Vec<&str> = key_columns
+        .iter()
+        .map(|&c| value_of(row, c, lines, first_line).unwrap_or(""))
+        .collect();
+    key.join("\u{1f}")
+}
+
+/// Group the value ranges of a loop into rows.
+/// A new row starts whenever the column index wraps around.
+fn split_rows(block: &LoopBlock) -> Vec<Vec<RowValue>> {
+    let mut rows: Vec<Vec<RowValue>> = Vec::new();
+    let mut current: Vec<RowValue> = Vec::new();
+    let mut last_col: Option<usize> = None;
+    let single_column = block.items.len() == 1;
+
+    for data_line in &block.data_lines {
+        for range in &data_line.value_ranges {
+            let wrapped = last_col.is_some_and(|last| {
+                range.column_index < last || (single_column && range.column_index == last)
+            });
+            if wrapped && !current.is_empty() {
+                rows.push(std::mem::take(&mut current));
+            }
+            current.push(RowValue {
+                line: data_line.line,
+                start: range.start,
+                length: range.length,
+                column_index: range.column_index,
+            });
+            last_col = Some(range.column_index);
+        }
+    }
+    if !current.is_empty() {
+        rows.push(current);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    const CIF: &str = r#"data_test
+loop_
+_atom_site.group_PDB
+_atom_site.id
+_atom_site.label_asym_id
+_atom_site.label_seq_id
+_atom_site.occupancy
+ATOM   1 A 1 1.00
+ATOM   2 A 2 0.50
+HETATM 3 B . 1.00
+"#;
+
+    fn row_rotations(result: &ParseResult, line: usize) -> Vec<u8> {
+        result
+            .tokens
+            .iter()
+            .filter(|t| t.line == line && t.item_name.is_some())
+            .map(|t| t.rotation)
+            .collect()
+    }
+
+    #[test]
+    fn test_color_by_chain() {
+        let mut result = parse(CIF);
+        let options = HighlightOptions {
+            mode: HighlightMode::Chain,
+            ..Default::default()
+        };
+        apply_highlight(&mut result, CIF, &options);
+
+        assert_eq!(row_rotations(&result, 7), vec![0; 5]);
+        assert_eq!(row_rotations(&result, 8), vec![0; 5]);
+        assert_eq!(row_rotations(&result, 9), vec![1; 5]);
+    }
+
+    #[test]
+    fn test_color_by_residue_alternates() {
+        let mut result = parse(CIF);
+        let options = HighlightOptions {
+            mode: HighlightMode::Residue,
+            ..Default::default()
+        };
+        apply_highlight(&mut result, CIF, &options);
+
+        assert_eq!(row_rotations(&result, 7)[0], 0);
+        assert_eq!(row_rotations(&result, 8)[0], 1);
+        assert_eq!(row_rotations(&result, 9)[0], 0);
+    }
+
+    #[test]
+    fn test_dim_partial_occupancy() {
+        let mut result = parse(CIF);
+        let options = HighlightOptions {
+            dim_partial_occupancy: true,
+            ..Default::default()
+        };
+        apply_highlight(&mut result, CIF, &options);
+
+        let dimmed_lines: Vec<usize> = result
+            .tokens
+            .iter()
+            .filter(|t| t.dimmed)
+            .map(|t| t.line)
+            .collect();
+        assert_eq!(dimmed_lines, vec![8; 5]);
+        // Column coloring is kept
+        assert_eq!(row_rotations(&result, 8), vec![0, 1, 2, 3, 4]);
+    }
+}
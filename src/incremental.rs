@@ -0,0 +1,178 @@
+//! Cooperative parsing of large inputs
+//! Parses a bounded number of bytes per step so callers can yield between steps
+
+use crate::highlight::apply_highlight;
+use crate::parser::{parse_chunk, ChunkState};
+use crate::{HighlightOptions, ParseError, ParseLimits, ParseResult};
+use serde::Serialize;
+use tsify::Tsify;
+
+/// Progress of an incremental parse
+#[derive(Serialize, Tsify, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub bytes_parsed: usize,
+    pub total_bytes: usize,
+    pub done: bool,
+}
+
+/// A parse advanced in steps with `step` and completed with `finish`.
+/// Gives the same result as `parse_with_options`.
+pub struct IncrementalParse {
+    text: String,
+    options: HighlightOptions,
+    limits: ParseLimits,
+    /// Byte offset and line number where the next step starts
+    offset: usize,
+    line: usize,
+    state: ChunkState,
+}
+
+impl IncrementalParse {
+    /// Start parsing `text`, enforcing `limits`
+    pub fn new(
+        text: String,
+        options: &HighlightOptions,
+        limits: &ParseLimits,
+    ) -> Result<Self, ParseError> {
+        limits.check_size(text.len())?;
+        Ok(IncrementalParse {
+            text,
+            options: *options,
+            limits: *limits,
+            offset: 0,
+            line: 0,
+            state: ChunkState::default(),
+        })
+    }
+
+    /// Parse about `max_bytes` more bytes, up to the next line start outside a text field
+    pub fn step(&mut self, max_bytes: usize) -> Result<Progress, ParseError> {
+        if self.offset < self.text.len() {
+            let (end, lines) = self.chunk_end(self.offset.saturating_add(max_bytes.max(1)));
+            parse_chunk(
+                &self.text[self.offset..end],
+                self.offset,
+                self.line,
+                &self.options,
+                &mut self.state,
+            );
+            self.offset = end;
+            self.line += lines;
+            if let Some(limit) = self.limits.max_tokens {
+                if self.state.tokens.len() > limit {
+                    return Err(ParseError::LimitExceeded { limit });
+                }
+            }
+        }
+        Ok(self.progress())
+    }
+
+    /// Current progress
+    pub fn progress(&self) -> Progress {
+        Progress {
+            bytes_parsed: self.offset,
+            total_bytes: self.text.len(),
+            done: self.offset >= self.text.len(),
+        }
+    }
+
+    /// Parse the rest of the input and return the result.
+    /// Structure-aware highlighting (`mode` other than column, `dim_partial_occupancy`)
+    /// runs here over the whole result in one blocking call, not in the steps.
+    pub fn finish(mut self) -> Result<ParseResult, ParseError> {
+        self.step(usize::MAX)?;
+        let mut result = self.state.finish();
+        apply_highlight(&mut result, &self.text, &self.options);
+        Ok(result)
+    }
+
+    /// First line start at or after `target` that is outside a text field,
+    /// and the number of lines before it since `offset`
+    fn chunk_end(&self, target: usize) -> (usize, usize) {
+        let mut in_text_field = false;
+        let mut position = self.offset;
+        let mut lines = 0;
+        for raw_line in self.text[self.offset..].split_inclusive('\n') {
+            if position >= target && !in_text_field {
+                break;
+            }
+            if raw_line.starts_with(';') {
+                in_text_field = !in_text_field;
+            }
+            position += raw_line.len();
+            lines += 1;
+        }
+        (position, lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_with_options;
+
+    const CIF: &str = "data_test\n_struct.title\n;\nline 1\nline 2\n;\nloop_\n_atom_site.id\n\
+_atom_site.label_asym_id\n1 A\n2\nA\n3 B\n_exptl.method 'X-RAY'\n";
+
+    fn debug(result: &ParseResult) -> String {
+        format!("{:?}", result)
+    }
+
+    #[test]
+    fn test_step_past_end() {
+        let mut parse = IncrementalParse::new(
+            CIF.to_string(),
+            &HighlightOptions::default(),
+            &ParseLimits::default(),
+        )
+        .unwrap();
+        parse.step(5).unwrap();
+        assert!(parse.step(usize::MAX).unwrap().done);
+    }
+
+    #[test]
+    fn test_matches_full_parse() {
+        let options = HighlightOptions {
+            mode: crate::HighlightMode::Chain,
+            ..Default::default()
+        };
+        let expected = debug(&parse_with_options(CIF, &options));
+        for max_bytes in [1, 7, 20, 1000] {
+            let mut parse =
+                IncrementalParse::new(CIF.to_string(), &options, &ParseLimits::default()).unwrap();
+            let mut steps = 0;
+            while !parse.step(max_bytes).unwrap().done {
+                steps += 1;
+            }
+            assert!(max_bytes > CIF.len() || steps > 1);
+            assert_eq!(
+                debug(&parse.finish().unwrap()),
+                expected,
+                "{} bytes",
+                max_bytes
+            );
+        }
+    }
+
+    #[test]
+    fn test_progress_and_limits() {
+        let limits = ParseLimits::default();
+        let mut parse =
+            IncrementalParse::new(CIF.to_string(), &HighlightOptions::default(), &limits).unwrap();
+        let progress = parse.step(15).unwrap();
+        // Stops after `_struct.title`, before the text field
+        assert_eq!((progress.bytes_parsed, progress.done), (24, false));
+        assert_eq!(progress.total_bytes, CIF.len());
+
+        let limits = ParseLimits {
+            max_tokens: Some(5),
+            ..Default::default()
+        };
+        let parse =
+            IncrementalParse::new(CIF.to_string(), &HighlightOptions::default(), &limits).unwrap();
+        assert!(matches!(
+            parse.finish(),
+            Err(ParseError::LimitExceeded { limit: 5 })
+        ));
+    }
+}
@@ -0,0 +1,285 @@
+//! Lazy documents for large files
+//! Only the byte offset of each value is recorded; values are decoded when a column is read
+
+use crate::document::{build_blocks, Cell};
+use crate::tokenizer::{classify_value, lexeme_at, unquote, LexemeKind, RawLexemes};
+use crate::{Category, DataBlock, Document, Value};
+use std::fmt;
+use std::str::Utf8Error;
+#[cfg(feature = "mmap")]
+use std::{fs::File, io, path::Path};
+
+/// Largest input whose offsets fit in `u32`
+pub const MAX_LAZY_LEN: usize = u32::MAX as usize;
+
+/// Byte offset stored for each value: `u32` for inputs up to [`MAX_LAZY_LEN`],
+/// `u64` for inputs of any size. Implemented for these two types only.
+pub trait Offset: Cell + Copy + fmt::Debug {
+    /// Longest input whose offsets fit
+    const MAX_LEN: usize;
+    /// Offset as an index into the source
+    fn index(self) -> usize;
+}
+
+impl Offset for u32 {
+    const MAX_LEN: usize = MAX_LAZY_LEN;
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl Offset for u64 {
+    const MAX_LEN: usize = usize::MAX;
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Document structure with values kept as byte offsets into the source.
+/// The source is anything holding the bytes: a `&str`, a `String` or a memory map.
+/// Only data names are decoded while parsing; values are checked to be UTF-8 when read.
+#[derive(Clone, Debug)]
+pub struct LazyDocument<S, O = u32> {
+    source: S,
+    pub blocks: Vec<DataBlock<O>>,
+}
+
+impl<S: AsRef<[u8]>> LazyDocument<S> {
+    /// Parse the structure of CIF text without decoding values, keeping `u32` offsets.
+    /// Lexemes are streamed into the builder, so only the offsets are kept.
+    ///
+    /// # Panics
+    /// If the text is longer than [`MAX_LAZY_LEN`]; use [`LazyDocument::parse_u64`] for such input.
+    pub fn parse(source: S) -> Self {
+        Self::build(source)
+    }
+}
+
+impl<S: AsRef<[u8]>> LazyDocument<S, u64> {
+    /// Like [`LazyDocument::parse`] with `u64` offsets, for input of any size
+    pub fn parse_u64(source: S) -> Self {
+        Self::build(source)
+    }
+}
+
+impl<S: AsRef<[u8]>, O: Offset> LazyDocument<S, O> {
+    fn build(source: S) -> Self {
+        let bytes = source.as_ref();
+        assert!(
+            bytes.len() <= O::MAX_LEN,
+            "input is too long for {}-bit offsets",
+            std::mem::size_of::<O>() * 8
+        );
+        let blocks = build_blocks(RawLexemes::new(bytes, 0, 0));
+        LazyDocument { source, blocks }
+    }
+
+    /// Source bytes
+    pub fn bytes(&self) -> &[u8] {
+        self.source.as_ref()
+    }
+
+    /// Raw bytes of the value at `offset`, quotes included, borrowed from the source
+    pub fn bytes_at(&self, offset: O) -> &[u8] {
+        lexeme_at(self.bytes(), offset.index()).1
+    }
+
+    /// Find a data block by name
+    pub fn block(&self, name: &str) -> Option<&DataBlock<O>> {
+        self.blocks.iter().find(|b| b.name == name)
+    }
+
+    /// First data block
+    pub fn first_block(&self) -> Option<&DataBlock<O>> {
+        self.blocks.first()
+    }
+
+    /// Text of the value at `offset`, borrowed from the source without quotes
+    pub fn str_at(&self, offset: O) -> Result<&str, Utf8Error> {
+        let (kind, raw) = lexeme_at(self.bytes(), offset.index());
+        Ok(unquote(kind, std::str::from_utf8(raw)?))
+    }
+
+    /// Decode the value at `offset`
+    pub fn value_at(&self, offset: O) -> Result<Value, Utf8Error> {
+        let (kind, raw) = lexeme_at(self.bytes(), offset.index());
+        Ok(Value::from_lexeme(kind, std::str::from_utf8(raw)?))
+    }
+
+    /// Numeric value at `offset`, ignoring any standard uncertainty; `None` for
+    /// nulls, quoted strings and text
+    pub fn f64_at(&self, offset: O) -> Option<f64> {
+        let (kind, raw) = lexeme_at(self.bytes(), offset.index());
+        let raw = std::str::from_utf8(raw).ok()?;
+        if kind != LexemeKind::Word || !classify_value(raw, false).is_number() {
+            return None;
+        }
+        raw.split('(').next().unwrap_or(raw).parse().ok()
+    }
+
+    /// Decode all values of one item; `None` if the item is missing
+    pub fn column(
+        &self,
+        category: &Category<O>,
+        item: &str,
+    ) -> Option<Result<Vec<Value>, Utf8Error>> {
+        let offsets = category.column(item)?;
+        Some(offsets.iter().map(|&o| self.value_at(o)).collect())
+    }
+
+    /// Numeric values of one item without allocating a [`Value`] per row
+    pub fn column_f64(&self, category: &Category<O>, item: &str) -> Option<Vec<Option<f64>>> {
+        let offsets = category.column(item)?;
+        Some(offsets.iter().map(|&o| self.f64_at(o)).collect())
+    }
+
+    /// Decode a whole category
+    pub fn category(&self, category: &Category<O>) -> Result<Category, Utf8Error> {
+        let mut decoded = Category::new(category.name.clone(), category.is_loop);
+        for (item, offsets) in category.items.iter().zip(&category.columns) {
+            let values = offsets
+                .iter()
+                .map(|&o| self.value_at(o))
+                .collect::<Result<_, _>>()?;
+            decoded.push_item(item.clone(), values);
+        }
+        Ok(decoded)
+    }
+
+    /// Decode every value, giving the same document as [`Document::parse`]
+    pub fn to_document(&self) -> Result<Document, Utf8Error> {
+        Ok(Document {
+            blocks: self
+                .blocks
+                .iter()
+                .map(|b| self.decode_block(b))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn decode_block(&self, block: &DataBlock<O>) -> Result<DataBlock, Utf8Error> {
+        Ok(DataBlock {
+            name: block.name.clone(),
+            categories: block
+                .categories
+                .iter()
+                .map(|c| self.category(c))
+                .collect::<Result<_, _>>()?,
+            frames: block
+                .frames
+                .iter()
+                .map(|f| self.decode_block(f))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Memory-mapped CIF file. Opening it reads no data; values are checked to be UTF-8 when read.
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub struct MappedText {
+    map: memmap2::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedText {
+    /// Map a file into memory. Compressed files cannot be mapped and give an error.
+    ///
+    /// The file must not be modified or truncated while it is mapped.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<MappedText> {
+        let file = File::open(path)?;
+        // SAFETY: the map is read-only; callers must not change the file while it is mapped
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        if crate::compression::Compression::detect(&map) != crate::compression::Compression::None {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "compressed input cannot be memory-mapped",
+            ));
+        }
+        Ok(MappedText { map })
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl AsRef<[u8]> for MappedText {
+    fn as_ref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Document {
+    /// Memory-map a file of any size and parse its structure in one streaming pass;
+    /// values are decoded on access and borrow from the map.
+    ///
+    /// Mapping is cheap, but the structure index is built before this returns: the
+    /// whole file is read and tokenized, taking time linear in its size, and one
+    /// `u64` offset is kept per value.
+    pub fn open_mmap(path: impl AsRef<Path>) -> io::Result<LazyDocument<MappedText, u64>> {
+        Ok(LazyDocument::parse_u64(MappedText::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CIF: &str = "data_1ABC\r\n_entry.id 1ABC\r\n_struct.title\r\n;Crystal structure\r\nof a protein\r\n;\r\n\
+loop_\n_atom_site.id\n_atom_site.type_symbol\n_atom_site.label_atom_id\n_atom_site.Cartn_x\n\
+1 N \"O5'\" 1.5(3) # first\n2 C 'it's' ?\n3 C\n;\n;\n-2e1\n\
+save_frame\n_item.name '_atom_site.id'\nsave_\n\
+data_second\n_cell_length_a 10.0(2)\n_note\n;unterminated\n";
+
+    #[test]
+    fn test_matches_document() {
+        let lazy = LazyDocument::parse(CIF);
+        assert_eq!(lazy.to_document().unwrap(), Document::parse(CIF));
+        let wide = LazyDocument::parse_u64(CIF.as_bytes());
+        assert_eq!(wide.to_document().unwrap(), Document::parse(CIF));
+    }
+
+    #[test]
+    fn test_column_access() {
+        let lazy = LazyDocument::parse(CIF);
+        let atom_site = lazy.first_block().unwrap().category("_atom_site").unwrap();
+        assert_eq!(atom_site.row_count(), 3);
+
+        assert_eq!(
+            lazy.column_f64(atom_site, "Cartn_x").unwrap(),
+            vec![Some(1.5), None, Some(-20.0)]
+        );
+        let names: Vec<&str> = atom_site
+            .column("label_atom_id")
+            .unwrap()
+            .iter()
+            .map(|&o| lazy.str_at(o).unwrap())
+            .collect();
+        assert_eq!(names, ["O5'", "it's", ""]);
+        assert!(lazy.column(atom_site, "missing").is_none());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_open_mmap() {
+        let path = std::env::temp_dir().join(format!("cifparse-mmap-{}.cif", std::process::id()));
+        std::fs::write(&path, CIF).unwrap();
+        let mapped = Document::open_mmap(&path).unwrap();
+        assert_eq!(mapped.to_document().unwrap(), Document::parse(CIF));
+
+        let entry = mapped.first_block().unwrap().category("_entry").unwrap();
+        assert_eq!(mapped.bytes_at(entry.columns[0][0]), b"1ABC");
+
+        // Invalid UTF-8 only fails when the value is read
+        std::fs::write(&path, b"data_x\n_a.b \xff\xfe\n_a.c ok\n").unwrap();
+        let mapped = Document::open_mmap(&path).unwrap();
+        let a = mapped.first_block().unwrap().category("_a").unwrap();
+        assert!(mapped.str_at(a.columns[0][0]).is_err());
+        assert_eq!(mapped.str_at(a.columns[1][0]), Ok("ok"));
+        assert!(mapped.to_document().is_err());
+
+        std::fs::write(&path, [0x1f, 0x8b, 8, 0]).unwrap();
+        let error = Document::open_mmap(&path).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,25 +1,141 @@
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod bcif;
+pub mod compression;
+pub mod csv;
+pub mod diagnostics;
+mod document;
+mod error;
+mod highlight;
+pub mod incremental;
+pub mod lazy;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod mmjson;
 mod parser;
+pub mod pdb;
+pub mod refactor;
+pub mod relations;
+pub mod semantic;
+pub mod symbols;
 mod tokenizer;
+mod viewport;
 mod wasm;
+pub mod writer;
 
 use serde::{Deserialize, Serialize};
+use tsify::Tsify;
 
+pub use document::{Category, DataBlock, Document, Value};
+pub use error::{ParseError, ParseLimits};
+pub use highlight::{HighlightMode, HighlightOptions};
+pub use tokenizer::{classify_value, special_split, split_data_name, split_spans, Span};
+pub use viewport::TokenIndex;
 // Re-export WASM bindings
-pub use wasm::CifParser;
+pub use wasm::{CifParser, EncodedTokens};
+
+/// Kind of a highlighting token.
+/// Serialized in snake_case (e.g. `"block_header"`) so themes can style each kind.
+#[derive(Serialize, Deserialize, Tsify, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenKind {
+    /// `data_`, `save_` or `global_` header
+    BlockHeader,
+    /// `loop_` keyword
+    LoopKeyword,
+    /// Category part of a data name (`_atom_site`)
+    Category,
+    /// Dot and item part of a data name (`.id`), rotated by item position
+    ItemName,
+    /// Value, rotated by column
+    Value,
+    /// Line of a `;` delimited text field, rotated by column
+    TextField,
+    /// `#` comment line
+    Comment,
+    /// `?` or `.` (only with `distinguish_value_kinds`)
+    Null,
+    /// Numeric value (only with `distinguish_value_kinds`)
+    Number,
+    /// Quoted string value (only with `distinguish_value_kinds`)
+    QuotedString,
+}
+
+/// Kind of a value token, used for highlighting and type-aware validation
+#[derive(Serialize, Deserialize, Tsify, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueKind {
+    /// Integer, e.g. `12`
+    Integer,
+    /// Float, e.g. `-1.5e3`
+    Float,
+    /// Number with standard uncertainty, e.g. `1.234(5)`
+    FloatWithSu,
+    /// `?`
+    Unknown,
+    /// `.`
+    Inapplicable,
+    /// `'...'`
+    SingleQuoted,
+    /// `"..."`
+    DoubleQuoted,
+    /// `;` delimited text field
+    TextField,
+    /// Unquoted word
+    Bare,
+}
+
+impl ValueKind {
+    /// Token kind used when value kinds are distinguished
+    pub fn token_kind(self) -> TokenKind {
+        match self {
+            ValueKind::Integer | ValueKind::Float | ValueKind::FloatWithSu => TokenKind::Number,
+            ValueKind::Unknown | ValueKind::Inapplicable => TokenKind::Null,
+            ValueKind::SingleQuoted | ValueKind::DoubleQuoted => TokenKind::QuotedString,
+            ValueKind::TextField => TokenKind::TextField,
+            ValueKind::Bare => TokenKind::Value,
+        }
+    }
+
+    /// Whether the value is `?` or `.`
+    pub fn is_null(self) -> bool {
+        matches!(self, ValueKind::Unknown | ValueKind::Inapplicable)
+    }
+
+    /// Whether the value is numeric
+    pub fn is_number(self) -> bool {
+        matches!(
+            self,
+            ValueKind::Integer | ValueKind::Float | ValueKind::FloatWithSu
+        )
+    }
+}
 
 /// Token information for syntax highlighting
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Tsify, Clone, Debug)]
 pub struct Token {
     pub line: usize,
     pub start: usize,
     pub length: usize,
-    pub token_type: u8,
+    pub kind: TokenKind,
+    /// Color rotation index (`0..rotation_length`) for item names, values and text fields
+    pub rotation: u8,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub item_name: Option<String>,
+    /// Classification of value and text field tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_kind: Option<ValueKind>,
+    /// Row is de-emphasized (e.g. partial occupancy)
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub dimmed: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
 }
 
 /// Item (field) information within a category
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Tsify, Clone, Debug)]
 pub struct Item {
     pub line: usize,
     pub start: usize,
@@ -28,22 +144,23 @@ pub struct Item {
 }
 
 /// Value range information
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Tsify, Clone, Debug)]
 pub struct ValueRange {
     pub start: usize,
     pub length: usize,
     pub column_index: usize,
+    pub kind: ValueKind,
 }
 
 /// Data line information
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Tsify, Clone, Debug)]
 pub struct DataLine {
     pub line: usize,
     pub value_ranges: Vec<ValueRange>,
 }
 
 /// Loop block information
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Tsify, Clone, Debug)]
 pub struct LoopBlock {
     pub start_line: usize,
     pub category_name: String,
@@ -55,7 +172,7 @@ pub struct LoopBlock {
 }
 
 /// Parse result containing loops and tokens
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Tsify, Debug)]
 pub struct ParseResult {
     pub loops: Vec<LoopBlock>,
     pub tokens: Vec<Token>,
@@ -63,11 +180,42 @@ pub struct ParseResult {
 
 /// Parse CIF text and return result
 pub fn parse(text: &str) -> ParseResult {
-    parser::parse_cif_internal(text)
+    parser::parse_cif_internal(text, &HighlightOptions::default())
+}
+
+/// Parse CIF text with the given highlighting options
+pub fn parse_with_options(text: &str, options: &HighlightOptions) -> ParseResult {
+    let mut result = parser::parse_cif_internal(text, options);
+    highlight::apply_highlight(&mut result, text, options);
+    result
+}
+
+/// Parse CIF text with highlighting options, enforcing `limits`
+pub fn parse_with_limits(
+    text: &str,
+    options: &HighlightOptions,
+    limits: &ParseLimits,
+) -> Result<ParseResult, ParseError> {
+    limits.check_size(text.len())?;
+    let mut result = match limits.max_tokens {
+        Some(limit) => parser::parse_cif_limited(text, options, limit)
+            .ok_or(ParseError::LimitExceeded { limit })?,
+        None => parser::parse_cif_internal(text, options),
+    };
+    highlight::apply_highlight(&mut result, text, options);
+    Ok(result)
+}
+
+/// Decompress and decode CIF bytes, enforcing `limits.max_input_bytes` on the
+/// decompressed size; decompression stops as soon as the limit is passed
+pub fn decode_bytes(bytes: &[u8], limits: &ParseLimits) -> Result<String, ParseError> {
+    let bytes =
+        compression::read_bytes(bytes, limits.max_input_bytes).map_err(ParseError::Decompress)?;
+    limits.check_size(bytes.len())?;
+    String::from_utf8(bytes).map_err(ParseError::InvalidUtf8)
 }
 
 /// Parse CIF text and return as JSON string
-pub fn parse_to_json(text: &str) -> String {
-    let result = parser::parse_cif_internal(text);
-    serde_json::to_string(&result).unwrap_or_default()
+pub fn parse_to_json(text: &str) -> serde_json::Result<String> {
+    serde_json::to_string(&parse(text))
 }
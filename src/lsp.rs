@@ -0,0 +1,663 @@
+//! Language server for CIF files
+//! Serves diagnostics with quick fixes, document symbols, workspace-wide symbol search,
+//! go-to-definition between parent and child items and renaming of chains and categories
+//! over stdio
+
+use crate::diagnostics::{diagnostics, utf16_diagnostics, Diagnostic, Severity};
+use crate::refactor::{rename_at, utf16_edits, TextEdit};
+use crate::relations::{definition, Relations};
+use crate::semantic::{byte_column, line_of, utf16_len};
+use crate::symbols::{document_symbols, IndexOptions, Symbol, SymbolKind, WorkspaceIndex};
+use crate::Document;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidChangeWatchedFiles, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{
+    CodeActionRequest, DocumentSymbolRequest, GotoDefinition, Rename, Request as _,
+    WorkspaceSymbolRequest,
+};
+use lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams,
+    CodeActionProviderCapability, CodeActionResponse, DiagnosticRelatedInformation,
+    DiagnosticSeverity, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse,
+    FileChangeType, GotoDefinitionParams, GotoDefinitionResponse, InitializeParams, Location,
+    NumberOrString, OneOf, Position, PublishDiagnosticsParams, Range, RenameParams,
+    ServerCapabilities, SymbolInformation, TextDocumentSyncCapability, TextDocumentSyncKind, Uri,
+    WorkspaceEdit, WorkspaceSymbolParams, WorkspaceSymbolResponse,
+};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Run the language server on stdin and stdout until the client shuts it down
+pub fn serve_stdio() -> Result<(), BoxError> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = serde_json::to_value(capabilities())?;
+    let params: InitializeParams = serde_json::from_value(connection.initialize(capabilities)?)?;
+    let mut server = Server::new(&params);
+    server.run(&connection)?;
+    // The writer thread ends once the connection's sender is dropped
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(OneOf::Left(true)),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        ..Default::default()
+    }
+}
+
+/// Server state: the workspace index, item relations and the text of open documents
+pub struct Server {
+    index: WorkspaceIndex,
+    /// Parent/child links followed by go-to-definition
+    relations: Relations,
+    /// Open documents by path, holding the editor's unsaved text
+    documents: HashMap<PathBuf, String>,
+}
+
+impl Server {
+    /// Create a server and index the workspace folders of `params`.
+    /// Relations come from the dictionary file named by the `dictionary` initialization
+    /// option, e.g. `{"dictionary": "/data/mmcif_pdbx.dic"}`, or the built-in mmCIF table.
+    pub fn new(params: &InitializeParams) -> Self {
+        let dictionary = params
+            .initialization_options
+            .as_ref()
+            .and_then(|o| o.get("dictionary")?.as_str())
+            .and_then(|path| Document::from_path(path).ok());
+        let mut server = Server {
+            index: WorkspaceIndex::new(IndexOptions::default()),
+            relations: dictionary.map_or_else(Relations::mmcif, |d| Relations::from_dictionary(&d)),
+            documents: HashMap::new(),
+        };
+        #[allow(deprecated)]
+        let roots: Vec<&Uri> = match (&params.workspace_folders, &params.root_uri) {
+            (Some(folders), _) => folders.iter().map(|f| &f.uri).collect(),
+            (None, Some(root)) => vec![root],
+            (None, None) => Vec::new(),
+        };
+        for root in roots.into_iter().filter_map(uri_to_path) {
+            // Unreadable folders are left out of the index
+            let _ = server.index.index_dir(root);
+        }
+        server
+    }
+
+    fn run(&mut self, connection: &Connection) -> Result<(), BoxError> {
+        for message in &connection.receiver {
+            match message {
+                Message::Request(request) => {
+                    if connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    let response = self.handle_request(request);
+                    connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(notification) => {
+                    if let Some(reply) = self.handle_notification(notification) {
+                        connection.sender.send(Message::Notification(reply))?;
+                    }
+                }
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Answer a request
+    pub fn handle_request(&mut self, request: Request) -> Response {
+        match request.method.as_str() {
+            WorkspaceSymbolRequest::METHOD => {
+                respond::<WorkspaceSymbolRequest>(request, |p| self.workspace_symbols(p))
+            }
+            DocumentSymbolRequest::METHOD => {
+                respond::<DocumentSymbolRequest>(request, |p| self.document_symbols(p))
+            }
+            GotoDefinition::METHOD => respond::<GotoDefinition>(request, |p| self.definition(p)),
+            Rename::METHOD => try_respond::<Rename>(request, |p| self.rename(p)),
+            CodeActionRequest::METHOD => {
+                respond::<CodeActionRequest>(request, |p| self.code_actions(p))
+            }
+            method => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unsupported request {}", method),
+            ),
+        }
+    }
+
+    /// Track open documents and watched files; returns the diagnostics to publish
+    /// for a document that was opened, changed or closed
+    pub fn handle_notification(&mut self, notification: Notification) -> Option<Notification> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params = notification_params::<DidOpenTextDocument>(notification)?;
+                self.set_text(&params.text_document.uri, params.text_document.text);
+                return self.publish_diagnostics(params.text_document.uri);
+            }
+            DidChangeTextDocument::METHOD => {
+                let mut params = notification_params::<DidChangeTextDocument>(notification)?;
+                // Full sync: the last change holds the whole text
+                let change = params.content_changes.pop()?;
+                self.set_text(&params.text_document.uri, change.text);
+                return self.publish_diagnostics(params.text_document.uri);
+            }
+            DidCloseTextDocument::METHOD => {
+                let params = notification_params::<DidCloseTextDocument>(notification)?;
+                let path = uri_to_path(&params.text_document.uri)?;
+                self.documents.remove(&path);
+                self.reindex(&path);
+                // Clear the diagnostics of the closed document
+                return Some(diagnostics_notification(
+                    params.text_document.uri,
+                    Vec::new(),
+                ));
+            }
+            DidChangeWatchedFiles::METHOD => {
+                if let Some(params) = notification_params::<DidChangeWatchedFiles>(notification) {
+                    for change in params.changes {
+                        let Some(path) = uri_to_path(&change.uri) else {
+                            continue;
+                        };
+                        if change.typ == FileChangeType::DELETED {
+                            self.index.remove(&path);
+                        } else if !self.documents.contains_key(&path) {
+                            self.reindex(&path);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn publish_diagnostics(&self, uri: Uri) -> Option<Notification> {
+        let text = self.documents.get(&uri_to_path(&uri)?)?;
+        let lines: Vec<&str> = text.lines().collect();
+        let found = utf16_diagnostics(diagnostics(text), &line_of(&lines))
+            .iter()
+            .map(|d| lsp_diagnostic(&uri, d))
+            .collect();
+        Some(diagnostics_notification(uri, found))
+    }
+
+    fn set_text(&mut self, uri: &Uri, text: String) {
+        if let Some(path) = uri_to_path(uri) {
+            self.index.update(path.clone(), &text);
+            self.documents.insert(path, text);
+        }
+    }
+
+    /// Re-read a file from disk, dropping it from the index if it is gone
+    fn reindex(&mut self, path: &Path) {
+        if self.index.index_file(path).is_err() {
+            self.index.remove(path);
+        }
+    }
+
+    fn workspace_symbols(&self, params: WorkspaceSymbolParams) -> Option<WorkspaceSymbolResponse> {
+        let symbols = self
+            .index
+            .search(&params.query)
+            .into_iter()
+            .filter_map(|(path, symbol)| {
+                let uri = path_to_uri(path)?;
+                let line = self
+                    .documents
+                    .get(path)
+                    .and_then(|t| t.lines().nth(symbol.line));
+                #[allow(deprecated)]
+                Some(SymbolInformation {
+                    name: symbol.name.clone(),
+                    kind: lsp_kind(symbol.kind),
+                    tags: None,
+                    deprecated: None,
+                    location: Location::new(uri, symbol_range(symbol, line)),
+                    container_name: container_name(symbol),
+                })
+            })
+            .collect();
+        Some(WorkspaceSymbolResponse::Flat(symbols))
+    }
+
+    /// Outline of a document: blocks containing categories containing key values
+    fn document_symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let path = uri_to_path(&params.text_document.uri)?;
+        let text = self.documents.get(&path)?;
+        let lines: Vec<&str> = text.lines().collect();
+        let mut outline: Vec<DocumentSymbol> = Vec::new();
+        for symbol in document_symbols(text, &IndexOptions::default()) {
+            nest(
+                &mut outline,
+                document_symbol(&symbol, lines.get(symbol.line).copied()),
+            );
+        }
+        Some(DocumentSymbolResponse::Nested(outline))
+    }
+
+    /// Parent row of a child value, or the parent data name of a child data name
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let position = params.text_document_position_params;
+        let uri = position.text_document.uri;
+        let text = self.documents.get(&uri_to_path(&uri)?)?;
+        let line = position.position.line as usize;
+        let column = byte_column(
+            text.lines().nth(line)?,
+            position.position.character as usize,
+        );
+        let found = definition(text, line, column, &self.relations)?;
+        let line_text = text.lines().nth(found.line)?;
+        let start = utf16_len(line_text, 0, found.start);
+        let end = start + utf16_len(line_text, found.start, found.start + found.length);
+        let line = found.line as u32;
+        let range = Range::new(Position::new(line, start), Position::new(line, end));
+        Some(GotoDefinitionResponse::Scalar(Location::new(uri, range)))
+    }
+
+    /// Quick fixes of the diagnostics on the lines of the requested range
+    fn code_actions(&self, params: CodeActionParams) -> Option<CodeActionResponse> {
+        let uri = params.text_document.uri;
+        let text = self.documents.get(&uri_to_path(&uri)?)?;
+        let range = params.range.start.line as usize..=params.range.end.line as usize;
+        let lines: Vec<&str> = text.lines().collect();
+        let actions = utf16_diagnostics(diagnostics(text), &line_of(&lines))
+            .into_iter()
+            .filter(|d| range.contains(&d.line))
+            .filter_map(|d| {
+                let diagnostic = lsp_diagnostic(&uri, &d);
+                let fix = d.fix?;
+                let edits = fix.edits.into_iter().map(lsp_edit).collect();
+                Some(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: fix.title,
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic]),
+                    edit: Some(WorkspaceEdit::new(HashMap::from([(uri.clone(), edits)]))),
+                    is_preferred: Some(true),
+                    ..Default::default()
+                }))
+            })
+            .collect();
+        Some(actions)
+    }
+
+    /// Rename the chain ID or category at a position in every related column
+    fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>, String> {
+        let position = params.text_document_position;
+        let uri = position.text_document.uri;
+        let Some(text) = uri_to_path(&uri).and_then(|path| self.documents.get(&path)) else {
+            return Ok(None);
+        };
+        let line = position.position.line as usize;
+        let lines: Vec<&str> = text.lines().collect();
+        let line_text = line_of(&lines);
+        let column = byte_column(line_text(line), position.position.character as usize);
+        let edits = rename_at(text, line, column, &params.new_name).map_err(|e| e.to_string())?;
+        if edits.is_empty() {
+            return Err("nothing to rename here; rename a chain ID or a category".to_string());
+        }
+        let edits = utf16_edits(edits, &line_text)
+            .into_iter()
+            .map(lsp_edit)
+            .collect();
+        Ok(Some(WorkspaceEdit::new(HashMap::from([(uri, edits)]))))
+    }
+}
+
+/// Append `node` under the last symbol of a higher outline level, if any
+fn nest(outline: &mut Vec<DocumentSymbol>, node: DocumentSymbol) {
+    let depth = |kind| match kind {
+        lsp_types::SymbolKind::MODULE => 0,
+        lsp_types::SymbolKind::STRUCT => 1,
+        _ => 2,
+    };
+    match outline.last_mut() {
+        Some(last) if depth(last.kind) < depth(node.kind) => {
+            nest(last.children.get_or_insert_with(Vec::new), node)
+        }
+        _ => outline.push(node),
+    }
+}
+
+/// Deserialize request parameters, call `handler` and wrap its result
+fn respond<R: lsp_types::request::Request>(
+    request: Request,
+    handler: impl FnOnce(R::Params) -> R::Result,
+) -> Response {
+    match serde_json::from_value::<R::Params>(request.params) {
+        Ok(params) => Response::new_ok(request.id, handler(params)),
+        Err(e) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, e.to_string()),
+    }
+}
+
+/// Like `respond`, answering with a request failure if `handler` fails
+fn try_respond<R: lsp_types::request::Request>(
+    request: Request,
+    handler: impl FnOnce(R::Params) -> Result<R::Result, String>,
+) -> Response {
+    match serde_json::from_value::<R::Params>(request.params).map(handler) {
+        Ok(Ok(result)) => Response::new_ok(request.id, result),
+        Ok(Err(message)) => Response::new_err(request.id, ErrorCode::RequestFailed as i32, message),
+        Err(e) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, e.to_string()),
+    }
+}
+
+fn notification_params<N: lsp_types::notification::Notification>(
+    notification: Notification,
+) -> Option<N::Params> {
+    serde_json::from_value(notification.params).ok()
+}
+
+fn lsp_kind(kind: SymbolKind) -> lsp_types::SymbolKind {
+    match kind {
+        SymbolKind::Block => lsp_types::SymbolKind::MODULE,
+        SymbolKind::Category => lsp_types::SymbolKind::STRUCT,
+        SymbolKind::Key => lsp_types::SymbolKind::KEY,
+    }
+}
+
+/// Data name of a key value, or the block of a category or save frame
+fn container_name(symbol: &Symbol) -> Option<String> {
+    match &symbol.data_name {
+        Some(data_name) => Some(data_name.clone()),
+        None if !symbol.block.is_empty() => Some(format!("data_{}", symbol.block)),
+        None => None,
+    }
+}
+
+#[allow(deprecated)]
+fn document_symbol(symbol: &Symbol, line: Option<&str>) -> DocumentSymbol {
+    let range = symbol_range(symbol, line);
+    DocumentSymbol {
+        name: symbol.name.clone(),
+        detail: symbol.data_name.clone(),
+        kind: lsp_kind(symbol.kind),
+        tags: None,
+        deprecated: None,
+        range,
+        selection_range: range,
+        children: None,
+    }
+}
+
+fn diagnostics_notification(uri: Uri, diagnostics: Vec<lsp_types::Diagnostic>) -> Notification {
+    let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+    Notification::new(PublishDiagnostics::METHOD.to_string(), params)
+}
+
+/// LSP diagnostic of a diagnostic in UTF-16 columns, with related locations in `uri`
+fn lsp_diagnostic(uri: &Uri, diagnostic: &Diagnostic) -> lsp_types::Diagnostic {
+    let range = |line: usize, start: usize, end: usize| {
+        let line = line as u32;
+        Range::new(
+            Position::new(line, start as u32),
+            Position::new(line, end as u32),
+        )
+    };
+    let related: Vec<DiagnosticRelatedInformation> = diagnostic
+        .related
+        .iter()
+        .map(|r| DiagnosticRelatedInformation {
+            location: Location::new(uri.clone(), range(r.line, r.start, r.end)),
+            message: r.message.clone(),
+        })
+        .collect();
+    let severity = match diagnostic.severity {
+        Severity::Error => DiagnosticSeverity::ERROR,
+        Severity::Warning => DiagnosticSeverity::WARNING,
+        Severity::Information => DiagnosticSeverity::INFORMATION,
+    };
+    lsp_types::Diagnostic {
+        range: range(diagnostic.line, diagnostic.start, diagnostic.end),
+        severity: Some(severity),
+        code: Some(NumberOrString::String(diagnostic.code.as_str().to_string())),
+        source: Some("cif".to_string()),
+        message: diagnostic.message.clone(),
+        related_information: (!related.is_empty()).then_some(related),
+        ..Default::default()
+    }
+}
+
+/// LSP edit of an edit in UTF-16 columns
+fn lsp_edit(edit: TextEdit) -> lsp_types::TextEdit {
+    let range = Range::new(
+        Position::new(edit.line as u32, edit.start as u32),
+        Position::new(edit.end_line as u32, edit.end as u32),
+    );
+    lsp_types::TextEdit::new(range, edit.new_text)
+}
+
+/// LSP range of a symbol on `line`. Columns are converted to UTF-16 when the line text is
+/// at hand; for files that are not open, byte columns are used (exact for ASCII lines).
+fn symbol_range(symbol: &Symbol, line: Option<&str>) -> Range {
+    let (start, length) = match line {
+        Some(line) => (
+            utf16_len(line, 0, symbol.start),
+            utf16_len(line, symbol.start, symbol.start + symbol.length),
+        ),
+        None => (symbol.start as u32, symbol.length as u32),
+    };
+    let line = symbol.line as u32;
+    Range::new(
+        Position::new(line, start),
+        Position::new(line, start + length),
+    )
+}
+
+/// Local path of a `file:` URI
+pub fn uri_to_path(uri: &Uri) -> Option<PathBuf> {
+    let path = uri.as_str().strip_prefix("file://")?;
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|h| std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(decoded) if byte == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// `file:` URI of a path, percent-encoding reserved characters
+pub fn path_to_uri(path: &Path) -> Option<Uri> {
+    let mut uri = String::from("file://");
+    for &byte in path.to_str()?.as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    Uri::from_str(&uri).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(method: &str, params: serde_json::Value) -> Request {
+        Request::new(1.into(), method.to_string(), params)
+    }
+
+    fn open(server: &mut Server, uri: &str, text: &str) -> Option<Notification> {
+        server.handle_notification(Notification::new(
+            DidOpenTextDocument::METHOD.to_string(),
+            json!({"textDocument": {"uri": uri, "languageId": "cif", "version": 1, "text": text}}),
+        ))
+    }
+
+    #[test]
+    fn test_uri_round_trip() {
+        let path = Path::new("/data/my ligands/ATP#1.cif");
+        let uri = path_to_uri(path).unwrap();
+        assert_eq!(uri.as_str(), "file:///data/my%20ligands/ATP%231.cif");
+        assert_eq!(uri_to_path(&uri).unwrap(), path);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut server = Server::new(&InitializeParams::default());
+        let uri = "file:///ws/ATP.cif";
+        open(
+            &mut server,
+            uri,
+            "data_ATP\n_chem_comp.id ATP\n_chem_comp.name 'é ATP'\nloop_\n_chem_comp_atom.atom_id\nPG\n",
+        );
+        open(
+            &mut server,
+            "file:///ws/1abc.cif",
+            "data_1ABC\n_entry.id 1ABC\n",
+        );
+
+        let response = server.handle_request(request(
+            WorkspaceSymbolRequest::METHOD,
+            json!({"query": "atp"}),
+        ));
+        let result = response.result.unwrap();
+        let names: Vec<(&str, &str)> = result
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| {
+                (
+                    s["name"].as_str().unwrap(),
+                    s["location"]["uri"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(names, vec![("ATP", uri), ("ATP", uri)]);
+
+        let response = server.handle_request(request(
+            DocumentSymbolRequest::METHOD,
+            json!({"textDocument": {"uri": uri}}),
+        ));
+        let outline = response.result.unwrap();
+        let block = &outline[0];
+        assert_eq!(block["name"], "ATP");
+        let categories = block["children"].as_array().unwrap();
+        assert_eq!(categories[0]["name"], "_chem_comp");
+        assert_eq!(categories[0]["children"][0]["detail"], "_chem_comp.id");
+        assert_eq!(categories[1]["name"], "_chem_comp_atom");
+
+        let response = server.handle_request(request("textDocument/hover", json!({})));
+        assert!(response.error.is_some());
+    }
+
+    #[test]
+    fn test_definition() {
+        let mut server = Server::new(&InitializeParams::default());
+        let uri = "file:///ws/1abc.cif";
+        open(
+            &mut server,
+            uri,
+            "data_1ABC\n_struct_asym.id 'é'\nloop_\n_atom_site.id\n_atom_site.label_asym_id\n1 'é'\n",
+        );
+        let response = server.handle_request(request(
+            GotoDefinition::METHOD,
+            json!({"textDocument": {"uri": uri}, "position": {"line": 5, "character": 3}}),
+        ));
+        assert_eq!(
+            response.result.unwrap(),
+            json!({"uri": uri, "range": {"start": {"line": 1, "character": 16}, "end": {"line": 1, "character": 19}}})
+        );
+
+        let response = server.handle_request(request(
+            GotoDefinition::METHOD,
+            json!({"textDocument": {"uri": uri}, "position": {"line": 5, "character": 0}}),
+        ));
+        assert_eq!(response.result.unwrap(), serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut server = Server::new(&InitializeParams::default());
+        let uri = "file:///ws/1abc.cif";
+        open(
+            &mut server,
+            uri,
+            "data_1ABC\n_struct_asym.id Ä\nloop_\n_atom_site.id\n_atom_site.label_asym_id\n1 Ä\n",
+        );
+        let rename = |server: &mut Server, line: u32, character: u32| {
+            server.handle_request(request(
+                Rename::METHOD,
+                json!({"textDocument": {"uri": uri}, "position": {"line": line, "character": character},
+                       "newName": "B"}),
+            ))
+        };
+        let response = rename(&mut server, 5, 2);
+        let edits = &response.result.unwrap()["changes"][uri];
+        assert_eq!(edits.as_array().unwrap().len(), 2);
+        assert_eq!(edits[1]["range"]["end"], json!({"line": 5, "character": 3}));
+
+        let response = rename(&mut server, 5, 0);
+        assert_eq!(
+            response.error.unwrap().code,
+            ErrorCode::RequestFailed as i32
+        );
+    }
+
+    #[test]
+    fn test_diagnostics_and_code_actions() {
+        let mut server = Server::new(&InitializeParams::default());
+        let uri = "file:///ws/1abc.cif";
+        let published = open(&mut server, uri, "data_x\n_struct.title Ü protein\n").unwrap();
+        assert_eq!(published.method, PublishDiagnostics::METHOD);
+        let diagnostic = &published.params["diagnostics"][0];
+        assert_eq!(diagnostic["code"], "value_without_name");
+        assert_eq!(
+            diagnostic["range"]["start"],
+            json!({"line": 1, "character": 16})
+        );
+
+        let response = server.handle_request(request(
+            CodeActionRequest::METHOD,
+            json!({"textDocument": {"uri": uri},
+                   "range": {"start": {"line": 1, "character": 0}, "end": {"line": 1, "character": 0}},
+                   "context": {"diagnostics": []}}),
+        ));
+        let actions = response.result.unwrap();
+        assert_eq!(actions[0]["title"], "Quote 'Ü protein'");
+        assert_eq!(actions[0]["kind"], "quickfix");
+        assert_eq!(
+            actions[0]["edit"]["changes"][uri][0]["newText"],
+            "'Ü protein'"
+        );
+
+        let closed = server
+            .handle_notification(Notification::new(
+                DidCloseTextDocument::METHOD.to_string(),
+                json!({"textDocument": {"uri": uri}}),
+            ))
+            .unwrap();
+        assert_eq!(closed.params["diagnostics"], json!([]));
+
+        let published = open(&mut server, uri, "data_x\n_a.id 1\n_a.id 2\n").unwrap();
+        let related = &published.params["diagnostics"][0]["relatedInformation"][0];
+        assert_eq!(
+            related["location"],
+            json!({"uri": uri, "range": {"start": {"line": 1, "character": 0}, "end": {"line": 1, "character": 5}}})
+        );
+        assert_eq!(related["message"], "first defined here");
+    }
+}
@@ -0,0 +1,229 @@
+//! mmJSON (PDBj-style) export and import
+//! `{"data_1ABC": {"atom_site": {"id": [1, 2], ...}}}` with column arrays and typed numbers
+
+use crate::{Category, DataBlock, Document, Value, ValueKind};
+use serde_json::{Map, Number, Value as Json};
+use std::fmt;
+
+/// Error while reading mmJSON
+#[derive(Debug)]
+pub enum MmJsonError {
+    /// Input is not valid JSON
+    Json(serde_json::Error),
+    /// JSON does not have the mmJSON layout
+    Layout(String),
+}
+
+impl fmt::Display for MmJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MmJsonError::Json(e) => write!(f, "invalid JSON: {}", e),
+            MmJsonError::Layout(message) => write!(f, "invalid mmJSON: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for MmJsonError {}
+
+impl Document {
+    /// Export the document in mmJSON layout.
+    /// `?` becomes `null` and `.` the string `"."`; integers and floats become JSON numbers.
+    pub fn to_mmjson(&self) -> Json {
+        let mut root = Map::new();
+        for block in &self.blocks {
+            root.insert(format!("data_{}", block.name), block_to_json(block));
+        }
+        Json::Object(root)
+    }
+
+    /// Export the document as an mmJSON string
+    pub fn to_mmjson_string(&self) -> String {
+        self.to_mmjson().to_string()
+    }
+
+    /// Rebuild a document from mmJSON.
+    /// `null` reads as `?` and `"."` as `.`, so a quoted `'.'` value comes back inapplicable.
+    pub fn from_mmjson(json: &Json) -> Result<Document, MmJsonError> {
+        let root = as_object(json, "document")?;
+        let mut doc = Document::default();
+        for (key, block) in root {
+            let name = key.strip_prefix("data_").unwrap_or(key);
+            doc.blocks.push(block_from_json(name, block)?);
+        }
+        Ok(doc)
+    }
+
+    /// Rebuild a document from an mmJSON string
+    pub fn from_mmjson_str(text: &str) -> Result<Document, MmJsonError> {
+        let json: Json = serde_json::from_str(text).map_err(MmJsonError::Json)?;
+        Document::from_mmjson(&json)
+    }
+}
+
+fn block_to_json(block: &DataBlock) -> Json {
+    let mut categories = Map::new();
+    for category in &block.categories {
+        let mut items = Map::new();
+        for (item, column) in category.items.iter().zip(&category.columns) {
+            items.insert(
+                item.clone(),
+                Json::Array(column.iter().map(value_to_json).collect()),
+            );
+        }
+        let name = category.name.strip_prefix('_').unwrap_or(&category.name);
+        categories.insert(name.to_string(), Json::Object(items));
+    }
+    for frame in &block.frames {
+        categories.insert(format!("save_{}", frame.name), block_to_json(frame));
+    }
+    Json::Object(categories)
+}
+
+fn value_to_json(value: &Value) -> Json {
+    match value.kind {
+        ValueKind::Unknown => Json::Null,
+        ValueKind::Inapplicable => Json::String(".".to_string()),
+        ValueKind::Integer => value
+            .text
+            .parse::<i64>()
+            .map(Json::from)
+            .unwrap_or_else(|_| Json::String(value.text.clone())),
+        ValueKind::Float => value
+            .text
+            .parse::<f64>()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(Json::Number)
+            .unwrap_or_else(|| Json::String(value.text.clone())),
+        // Keep standard uncertainties and strings as text
+        _ => Json::String(value.text.clone()),
+    }
+}
+
+fn block_from_json(name: &str, json: &Json) -> Result<DataBlock, MmJsonError> {
+    let mut block = DataBlock::new(name);
+    for (key, content) in as_object(json, name)? {
+        if let Some(frame) = key.strip_prefix("save_") {
+            block.frames.push(block_from_json(frame, content)?);
+            continue;
+        }
+        let category_name = if key.is_empty() {
+            String::new()
+        } else {
+            format!("_{}", key)
+        };
+        let mut category = Category::new(category_name, false);
+        for (item, column) in as_object(content, key)? {
+            let Json::Array(values) = column else {
+                return Err(MmJsonError::Layout(format!(
+                    "{}.{} is not an array",
+                    key, item
+                )));
+            };
+            let values = values
+                .iter()
+                .map(|v| value_from_json(v, key, item))
+                .collect::<Result<Vec<_>, _>>()?;
+            category.push_item(item.clone(), values);
+        }
+        category.is_loop = category.row_count() > 1;
+        block.categories.push(category);
+    }
+    Ok(block)
+}
+
+fn value_from_json(json: &Json, category: &str, item: &str) -> Result<Value, MmJsonError> {
+    match json {
+        Json::Null => Ok(Value::unknown()),
+        Json::Number(n) => Ok(Value::new(n.to_string())),
+        Json::Bool(b) => Ok(Value::new(b.to_string())),
+        Json::String(s) if s == "." => Ok(Value::new(".")),
+        Json::String(s) => Ok(Value::from_text(s.clone())),
+        _ => Err(MmJsonError::Layout(format!(
+            "{}.{} holds a nested value",
+            category, item
+        ))),
+    }
+}
+
+fn as_object<'a>(json: &'a Json, what: &str) -> Result<&'a Map<String, Json>, MmJsonError> {
+    json.as_object()
+        .ok_or_else(|| MmJsonError::Layout(format!("{} is not an object", what)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CIF: &str = r#"data_1ABC
+_entry.id 1ABC
+_struct.title 'A title'
+loop_
+_atom_site.id
+_atom_site.type_symbol
+_atom_site.Cartn_x
+_atom_site.occupancy
+_atom_site.label_alt_id
+1 N 1.5 1.0(2) .
+2 C -3 ? A
+"#;
+
+    #[test]
+    fn test_export() {
+        let json = Document::parse(CIF).to_mmjson();
+        let atom_site = &json["data_1ABC"]["atom_site"];
+        assert_eq!(atom_site["id"], serde_json::json!([1, 2]));
+        assert_eq!(atom_site["type_symbol"], serde_json::json!(["N", "C"]));
+        assert_eq!(atom_site["Cartn_x"], serde_json::json!([1.5, -3]));
+        assert_eq!(atom_site["occupancy"], serde_json::json!(["1.0(2)", null]));
+        assert_eq!(atom_site["label_alt_id"], serde_json::json!([".", "A"]));
+        assert_eq!(
+            json["data_1ABC"]["entry"]["id"],
+            serde_json::json!(["1ABC"])
+        );
+
+        // Item order is preserved
+        let items: Vec<&String> = atom_site.as_object().unwrap().keys().collect();
+        assert_eq!(
+            items,
+            ["id", "type_symbol", "Cartn_x", "occupancy", "label_alt_id"]
+        );
+    }
+
+    #[test]
+    fn test_import() {
+        let text = Document::parse(CIF).to_mmjson_string();
+        let doc = Document::from_mmjson_str(&text).unwrap();
+        let block = doc.block("1ABC").unwrap();
+        assert_eq!(block.value("_struct.title").unwrap().text, "A title");
+
+        let atom_site = block.category("_atom_site").unwrap();
+        assert!(atom_site.is_loop);
+        assert_eq!(atom_site.value("Cartn_x", 0).unwrap().as_f64(), Some(1.5));
+        assert_eq!(atom_site.value("id", 1).unwrap().as_i64(), Some(2));
+        assert_eq!(
+            atom_site.value("occupancy", 1).unwrap().kind,
+            ValueKind::Unknown
+        );
+        assert_eq!(
+            atom_site.value("label_alt_id", 0).unwrap().kind,
+            ValueKind::Inapplicable
+        );
+
+        // Round trip through CIF text keeps the data
+        let written = crate::writer::write_cif(&doc);
+        assert_eq!(Document::parse(&written), doc);
+    }
+
+    #[test]
+    fn test_invalid_layout() {
+        assert!(matches!(
+            Document::from_mmjson_str("[1, 2]"),
+            Err(MmJsonError::Layout(_))
+        ));
+        assert!(matches!(
+            Document::from_mmjson_str("{"),
+            Err(MmJsonError::Json(_))
+        ));
+    }
+}
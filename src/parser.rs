@@ -1,489 +1,534 @@
 //! CIF parser implementation
 //! Parses CIF text and extracts loops and tokens for syntax highlighting
 
-use crate::tokenizer::{is_block_keyword, is_data_name, is_loop_keyword, special_split};
-use crate::{DataLine, Item, LoopBlock, ParseResult, Token, ValueRange};
-use regex::Regex;
+use crate::highlight::{is_atom_site_loop, GroupState};
+use crate::tokenizer::{
+    classify_value, is_block_keyword, is_data_name, is_loop_keyword, lexemes, split_data_name,
+    tokenize, tokenize_lines, Lexeme, LexemeKind,
+};
+use crate::{
+    DataLine, HighlightOptions, Item, LoopBlock, ParseResult, Token, TokenKind, ValueKind,
+    ValueRange,
+};
 
 /// Parse CIF text and return loops and tokens
-pub fn parse_cif_internal(text: &str) -> ParseResult {
-    let mut loops: Vec<LoopBlock> = Vec::new();
-    let mut tokens: Vec<Token> = Vec::new();
-    let mut current_loop: Option<LoopBlock> = None;
-    let mut multi_line_mode = false;
-
-    // For non-loop items color rotation
-    let mut last_category = String::new();
-    let mut category_item_count: usize = 0;
-
-    // Regex for category.field pattern
-    let category_field_re = Regex::new(r"^(_[A-Za-z0-9_]+)\.([A-Za-z0-9_\[\]]+)$").unwrap();
-    let leading_category_re =
-        Regex::new(r"^(\s*)(_[A-Za-z0-9_]+)\.([A-Za-z0-9_\[\]]+)").unwrap();
-
-    let lines: Vec<&str> = text.lines().collect();
-
-    for (line_num, line_text) in lines.iter().enumerate() {
-        let first_char = line_text.chars().next();
-
-        // Skip comment lines
-        if first_char == Some('#') {
-            if let Some(ref mut current) = current_loop {
-                if !current.items.is_empty() && current.names_defined {
-                    loops.push(current.clone());
-                    current_loop = None;
-                }
-            }
-            // rainbow10 for comments
-            tokens.push(Token {
-                line: line_num,
-                start: 0,
-                length: line_text.len(),
-                token_type: 10,
-                item_name: None,
-            });
-            continue;
+pub fn parse_cif_internal(text: &str, options: &HighlightOptions) -> ParseResult {
+    let mut state = ParseState::new(text, 0, options);
+    for lexeme in tokenize(text) {
+        state.feed(&lexeme);
+    }
+    state.finish()
+}
+
+/// Parse CIF text, giving up once more than `max_tokens` tokens are produced
+pub fn parse_cif_limited(
+    text: &str,
+    options: &HighlightOptions,
+    max_tokens: usize,
+) -> Option<ParseResult> {
+    let lexemes = tokenize(text);
+    if lexemes.len() > max_tokens {
+        return None;
+    }
+    let mut state = ParseState::new(text, 0, options);
+    for lexeme in lexemes {
+        state.feed(&lexeme);
+        if state.tokens.len() > max_tokens {
+            return None;
         }
+    }
+    Some(state.finish())
+}
 
-        // Handle multi-line strings (semicolon delimiter)
-        if first_char == Some(';') {
-            if multi_line_mode {
-                // End of multi-line string
-                multi_line_mode = false;
-                let token_type = if let Some(ref mut current) = current_loop {
-                    let field_count = current.items.len().max(1);
-                    let col_index = if current.is_in_loop_block {
-                        current.processed_value_count % field_count
-                    } else {
-                        0
-                    };
-                    let tt = if current.is_in_loop_block {
-                        1 + (col_index % 8) as u8
-                    } else {
-                        2
-                    };
-
-                    current.data_lines.push(DataLine {
-                        line: line_num,
-                        value_ranges: vec![ValueRange {
-                            start: 0,
-                            length: line_text.len(),
-                            column_index: col_index,
-                        }],
-                    });
-                    current.processed_value_count += 1;
-                    tt
-                } else {
-                    2
+/// Lines between periodic checkpoints inside a long loop or block
+const CHECKPOINT_INTERVAL: usize = 256;
+
+/// Grammar state at the start of a line, from which parsing can resume
+#[derive(Clone, Debug)]
+pub(crate) struct Checkpoint {
+    pub line: usize,
+    /// Active block without its data lines
+    pub current_loop: Option<LoopBlock>,
+    pending_item: Option<usize>,
+    /// Group numbering of the active `_atom_site` loop's rows before `line`,
+    /// or None if the line starts inside a row
+    pub groups: Option<GroupState>,
+}
+
+/// Checkpoints of `text`: one at line 0, one wherever the active block changes and
+/// one every `CHECKPOINT_INTERVAL` lines at a row boundary. Never inside a text field.
+pub(crate) fn checkpoints(text: &str, options: &HighlightOptions) -> Vec<Checkpoint> {
+    let mut state = ParseState::new(text, 0, options);
+    let mut checkpoints = vec![state.checkpoint(0, Some(GroupState::default()))];
+    let mut last_line = 0;
+
+    for lexeme in lexemes(text, 0, 0) {
+        if lexeme.line > last_line {
+            let last = &checkpoints[checkpoints.len() - 1];
+            let block_changed = block_key(&state.current_loop) != block_key(&last.current_loop);
+            let due = lexeme.line - last.line >= CHECKPOINT_INTERVAL && state.at_row_boundary();
+            if block_changed || due {
+                let groups = match block_changed {
+                    true => Some(GroupState::default()),
+                    false => last.groups.clone(),
                 };
+                let groups = state.advance_groups(groups);
+                checkpoints.push(state.checkpoint(lexeme.line, groups));
+                state.discard_output();
+            }
+        }
+        // A value may follow a text field on its closing line
+        last_line = lexeme.end_line;
+        state.feed(&lexeme);
+    }
+    checkpoints
+}
 
-                let item_name = get_current_item_name(&current_loop);
-                tokens.push(Token {
-                    line: line_num,
-                    start: 0,
-                    length: line_text.len(),
-                    token_type,
-                    item_name,
-                });
+/// Parse `text`, the source from the line of `checkpoint` on, resuming its state.
+/// Positions in the result are relative to the full source.
+pub(crate) fn parse_from(
+    text: &str,
+    base: usize,
+    checkpoint: &Checkpoint,
+    options: &HighlightOptions,
+) -> ParseResult {
+    let mut state = ParseState::new(text, checkpoint.line, options);
+    state.current_loop = checkpoint.current_loop.clone();
+    state.pending_item = checkpoint.pending_item;
+    for lexeme in tokenize_lines(text, base, checkpoint.line) {
+        state.feed(&lexeme);
+    }
+    state.finish()
+}
 
-                if let Some(ref mut current) = current_loop {
-                    if !current.items.is_empty() {
-                        current.names_defined = true;
-                    }
-                }
-            } else {
-                // Start of multi-line string
-                multi_line_mode = true;
-                let token_type = if let Some(ref mut current) = current_loop {
-                    let field_count = current.items.len().max(1);
-                    let col_index = if current.is_in_loop_block {
-                        current.processed_value_count % field_count
-                    } else {
-                        0
-                    };
-                    let tt = if current.is_in_loop_block {
-                        1 + (col_index % 8) as u8
-                    } else {
-                        2
-                    };
-
-                    current.data_lines.push(DataLine {
-                        line: line_num,
-                        value_ranges: vec![ValueRange {
-                            start: 0,
-                            length: line_text.len(),
-                            column_index: col_index,
-                        }],
-                    });
-                    tt
-                } else {
-                    2
-                };
+/// Output and grammar state carried between the chunks of a cooperative parse
+#[derive(Default)]
+pub(crate) struct ChunkState {
+    pub loops: Vec<LoopBlock>,
+    pub tokens: Vec<Token>,
+    current_loop: Option<LoopBlock>,
+    pending_item: Option<usize>,
+}
 
-                let item_name = get_current_item_name(&current_loop);
-                tokens.push(Token {
-                    line: line_num,
-                    start: 0,
-                    length: line_text.len(),
-                    token_type,
-                    item_name,
-                });
+impl ChunkState {
+    /// Close the active block and return everything parsed
+    pub fn finish(mut self) -> ParseResult {
+        if let Some(current) = self.current_loop.take() {
+            if !current.items.is_empty() {
+                self.loops.push(current);
             }
-            continue;
         }
+        ParseResult {
+            loops: self.loops,
+            tokens: self.tokens,
+        }
+    }
+}
 
-        // Inside multi-line string
-        if multi_line_mode {
-            if let Some(ref mut current) = current_loop {
-                let field_count = current.items.len().max(1);
-                let col_index = if current.is_in_loop_block {
-                    current.processed_value_count % field_count
-                } else {
-                    0
-                };
+/// Parse `text`, the lines of the source from `first_line` on starting at byte `base`,
+/// continuing `chunk`. Chunks must end at line starts outside text fields.
+pub(crate) fn parse_chunk(
+    text: &str,
+    base: usize,
+    first_line: usize,
+    options: &HighlightOptions,
+    chunk: &mut ChunkState,
+) {
+    let mut state = ParseState::new(text, first_line, options);
+    state.restore(std::mem::take(chunk));
+    for lexeme in tokenize_lines(text, base, first_line) {
+        state.feed(&lexeme);
+    }
+    *chunk = ChunkState {
+        loops: state.loops,
+        tokens: state.tokens,
+        current_loop: state.current_loop,
+        pending_item: state.pending_item,
+    };
+}
 
-                current.data_lines.push(DataLine {
-                    line: line_num,
-                    value_ranges: vec![ValueRange {
-                        start: 0,
-                        length: line_text.len(),
-                        column_index: col_index,
-                    }],
-                });
+/// Identity of the active block
+fn block_key(block: &Option<LoopBlock>) -> Option<(usize, &str)> {
+    block
+        .as_ref()
+        .map(|b| (b.start_line, b.category_name.as_str()))
+}
 
-                if !line_text.is_empty() {
-                    let token_type = if current.is_in_loop_block {
-                        1 + (col_index % 8) as u8
-                    } else {
-                        2
-                    };
-                    let item_name = if !current.items.is_empty() {
-                        let field_info = &current.items[col_index.min(current.items.len() - 1)];
-                        Some(format!("{}.{}", current.category_name, field_info.name))
-                    } else {
-                        None
-                    };
-                    tokens.push(Token {
-                        line: line_num,
-                        start: 0,
-                        length: line_text.len(),
-                        token_type,
-                        item_name,
-                    });
-                }
-            }
-            continue;
+/// Lines of a source split as the token stream reaches them, so that only
+/// those since the last `forget` are held
+struct Lines<'a> {
+    /// Source after the reached lines
+    rest: &'a str,
+    /// Line number of `window[0]`
+    first: usize,
+    window: Vec<&'a str>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str, first_line: usize) -> Self {
+        Lines {
+            rest: text,
+            first: first_line,
+            window: Vec::new(),
         }
+    }
 
-        // Skip empty lines
-        let trimmed = line_text.trim();
-        if trimmed.is_empty() {
-            if let Some(ref mut current) = current_loop {
-                if !current.items.is_empty() && !current.names_defined {
-                    current.names_defined = true;
+    /// Split lines up to and including `line`
+    fn reach(&mut self, line: usize) {
+        while self.first + self.window.len() <= line && !self.rest.is_empty() {
+            let next = match self.rest.split_once('\n') {
+                Some((next, rest)) => {
+                    self.rest = rest;
+                    next.strip_suffix('\r').unwrap_or(next)
                 }
-            }
-            continue;
+                None => std::mem::take(&mut self.rest),
+            };
+            self.window.push(next);
         }
+    }
 
-        let line_tokens = special_split(trimmed);
-        if line_tokens.is_empty() {
-            continue;
-        }
+    /// Text of a reached line, or "" if it was forgotten or is past the end
+    fn get(&self, line: usize) -> &'a str {
+        line.checked_sub(self.first)
+            .and_then(|i| self.window.get(i))
+            .copied()
+            .unwrap_or("")
+    }
 
-        // Check for block keyword (data_, save_, global_)
-        if is_block_keyword(&line_tokens[0].0, line_tokens[0].1) {
-            if let Some(ref current) = current_loop {
-                if !current.items.is_empty() {
-                    loops.push(current.clone());
-                }
-            }
-            current_loop = None;
-
-            // rainbow8 for section/heading
-            if let Some(idx) = line_text.find(&line_tokens[0].0) {
-                tokens.push(Token {
-                    line: line_num,
-                    start: idx,
-                    length: line_tokens[0].0.len(),
-                    token_type: 8,
-                    item_name: None,
-                });
-            }
-            continue;
+    /// Drop the reached lines
+    fn forget(&mut self) {
+        self.first += self.window.len();
+        self.window.clear();
+    }
+}
+
+/// Grammar state while walking the token stream.
+/// Line breaks carry no meaning; positions come from the lexemes.
+struct ParseState<'a> {
+    /// Lines of the source reached so far
+    lines: Lines<'a>,
+    options: &'a HighlightOptions,
+    loops: Vec<LoopBlock>,
+    tokens: Vec<Token>,
+    /// Loop or key-value category being filled
+    current_loop: Option<LoopBlock>,
+    /// Key-value item of `current_loop` still waiting for its value
+    pending_item: Option<usize>,
+}
+
+impl<'a> ParseState<'a> {
+    fn new(text: &'a str, first_line: usize, options: &'a HighlightOptions) -> Self {
+        ParseState {
+            lines: Lines::new(text, first_line),
+            options,
+            loops: Vec::new(),
+            tokens: Vec::new(),
+            current_loop: None,
+            pending_item: None,
         }
+    }
 
-        // Check for loop keyword
-        if is_loop_keyword(&line_tokens[0].0, line_tokens[0].1) {
-            if let Some(ref current) = current_loop {
-                if !current.items.is_empty() {
-                    loops.push(current.clone());
+    fn feed(&mut self, lexeme: &Lexeme) {
+        self.lines.reach(lexeme.end_line);
+        match lexeme.kind {
+            LexemeKind::Comment => self.push_token(lexeme, TokenKind::Comment, 0, None, None),
+            LexemeKind::Quoted | LexemeKind::TextField => self.value(lexeme),
+            LexemeKind::Word => {
+                if is_block_keyword(lexeme.text, false) {
+                    self.finish_loop();
+                    self.push_token(lexeme, TokenKind::BlockHeader, 0, None, None);
+                } else if is_loop_keyword(lexeme.text, false) {
+                    self.loop_keyword(lexeme);
+                } else if is_data_name(lexeme.text, false) {
+                    self.data_name(lexeme);
+                } else {
+                    self.value(lexeme);
                 }
             }
-            current_loop = Some(LoopBlock {
-                start_line: line_num,
-                category_name: String::new(),
-                items: Vec::new(),
-                names_defined: false,
-                is_in_loop_block: true,
-                processed_value_count: 0,
-                data_lines: Vec::new(),
-            });
-
-            // Reset category tracking
-            last_category.clear();
-            category_item_count = 0;
-
-            // rainbow6 for control
-            if let Some(idx) = line_text.find(&line_tokens[0].0) {
-                tokens.push(Token {
-                    line: line_num,
-                    start: idx,
-                    length: line_tokens[0].0.len(),
-                    token_type: 6,
-                    item_name: None,
-                });
-            }
-            continue;
         }
+    }
 
-        // Check for data name (_category.field)
-        if is_data_name(&line_tokens[0].0, line_tokens[0].1) {
-            let data_name = &line_tokens[0].0;
-
-            if let Some(caps) = category_field_re.captures(data_name) {
-                let category_name = caps.get(1).unwrap().as_str().to_string();
-                let name = caps.get(2).unwrap().as_str().to_string();
-
-                if let Some(lead_caps) = leading_category_re.captures(line_text) {
-                    let leading_spaces = lead_caps.get(1).map_or(0, |m| m.as_str().len());
-                    let field_start = leading_spaces + category_name.len() + 1;
-                    let field_length = name.len();
-
-                    // Handle category transitions for non-loop blocks
-                    if let Some(ref current) = current_loop {
-                        if current.names_defined && !current.items.is_empty() {
-                            if current.category_name != category_name
-                                || (current.category_name == category_name
-                                    && current.items.len() == 1)
-                            {
-                                loops.push(current.clone());
-                                current_loop = None;
-                            }
-                        }
-                    }
+    fn finish(mut self) -> ParseResult {
+        self.finish_loop();
+        ParseResult {
+            loops: self.loops,
+            tokens: self.tokens,
+        }
+    }
 
-                    if current_loop.is_none() {
-                        current_loop = Some(LoopBlock {
-                            start_line: line_num,
-                            category_name: category_name.clone(),
-                            items: Vec::new(),
-                            names_defined: true,
-                            is_in_loop_block: false,
-                            processed_value_count: 0,
-                            data_lines: Vec::new(),
-                        });
-                    }
+    fn restore(&mut self, chunk: ChunkState) {
+        self.loops = chunk.loops;
+        self.tokens = chunk.tokens;
+        self.current_loop = chunk.current_loop;
+        self.pending_item = chunk.pending_item;
+    }
 
-                    let current = current_loop.as_mut().unwrap();
+    fn checkpoint(&self, line: usize, groups: Option<GroupState>) -> Checkpoint {
+        let mut current_loop = self.current_loop.clone();
+        if let Some(current) = current_loop.as_mut() {
+            current.data_lines.clear();
+        }
+        Checkpoint {
+            line,
+            current_loop,
+            pending_item: self.pending_item,
+            groups,
+        }
+    }
 
-                    if current.category_name.is_empty() {
-                        current.category_name = category_name.clone();
-                    }
+    /// `groups` advanced over the rows of the active `_atom_site` loop parsed since
+    /// the last checkpoint, or None if that loop stops inside a row
+    fn advance_groups(&self, groups: Option<GroupState>) -> Option<GroupState> {
+        let Some(block) = self.current_loop.as_ref().filter(|b| is_atom_site_loop(b)) else {
+            return Some(GroupState::default());
+        };
+        let mut groups = groups.filter(|_| self.at_row_boundary())?;
+        groups.advance(
+            block,
+            &self.lines.window,
+            self.lines.first,
+            self.options.mode,
+        );
+        Some(groups)
+    }
 
-                    if current.category_name != category_name {
-                        if !current.items.is_empty() {
-                            loops.push(current.clone());
-                        }
-                        *current = LoopBlock {
-                            start_line: line_num,
-                            category_name: category_name.clone(),
-                            items: Vec::new(),
-                            names_defined: !current.is_in_loop_block,
-                            is_in_loop_block: current.is_in_loop_block,
-                            processed_value_count: 0,
-                            data_lines: Vec::new(),
-                        };
-                    }
+    /// Whether the next value starts a new loop row (or there is no loop)
+    fn at_row_boundary(&self) -> bool {
+        self.current_loop.as_ref().is_none_or(|c| {
+            !c.is_in_loop_block
+                || c.items.is_empty()
+                || c.processed_value_count % c.items.len() == 0
+        })
+    }
 
-                    current.items.push(Item {
-                        line: line_num,
-                        start: field_start,
-                        length: field_length,
-                        name: name.clone(),
-                    });
+    /// Drop tokens, finished blocks and lines reached so far, keeping the grammar state
+    fn discard_output(&mut self) {
+        self.lines.forget();
+        self.tokens.clear();
+        self.loops.clear();
+        if let Some(current) = self.current_loop.as_mut() {
+            current.data_lines.clear();
+        }
+    }
 
-                    // Track category item count for non-loop color rotation
-                    let color_base_index = if !current.is_in_loop_block {
-                        if category_name != last_category {
-                            category_item_count = 0;
-                            last_category = category_name.clone();
-                        }
-                        let idx = category_item_count;
-                        category_item_count += 1;
-                        idx
-                    } else {
-                        0
-                    };
-
-                    // Generate tokens for category and field name
-                    let field_index = current.items.len() - 1;
-                    let token_type_index = if current.is_in_loop_block {
-                        2 + (field_index % 7) as u8
-                    } else {
-                        2 + (color_base_index % 7) as u8
-                    };
-
-                    // Category part -> rainbow1
-                    tokens.push(Token {
-                        line: line_num,
-                        start: leading_spaces,
-                        length: category_name.len(),
-                        token_type: 1,
-                        item_name: None,
-                    });
+    /// Push the current loop if it has items
+    fn finish_loop(&mut self) {
+        self.pending_item = None;
+        if let Some(current) = self.current_loop.take() {
+            if !current.items.is_empty() {
+                self.loops.push(current);
+            }
+        }
+    }
 
-                    // Dot + Field Name -> rotating color
-                    tokens.push(Token {
-                        line: line_num,
-                        start: leading_spaces + category_name.len(),
-                        length: 1 + field_length,
-                        token_type: token_type_index,
-                        item_name: None,
-                    });
+    fn loop_keyword(&mut self, lexeme: &Lexeme) {
+        self.finish_loop();
+        self.current_loop = Some(new_block(lexeme.line, String::new(), true));
+        self.push_token(lexeme, TokenKind::LoopKeyword, 0, None, None);
+    }
 
-                    // Process values on the same line
-                    if line_tokens.len() > 1 {
-                        let token_text = &line_tokens[1].0;
-                        let search_start = lead_caps.get(0).map_or(0, |m| m.end());
-                        if let Some(rel_idx) = line_text[search_start..].find(token_text.as_str()) {
-                            let idx = search_start + rel_idx;
-                            let column_index = color_base_index;
-
-                            current.data_lines.push(DataLine {
-                                line: line_num,
-                                value_ranges: vec![ValueRange {
-                                    start: idx,
-                                    length: token_text.len(),
-                                    column_index,
-                                }],
-                            });
-                            current.processed_value_count += 1;
-
-                            let val_token_type = 2 + (column_index % 7) as u8;
-                            let item_name = Some(format!(
-                                "{}.{}",
-                                current.category_name,
-                                current.items[field_index].name
-                            ));
-                            tokens.push(Token {
-                                line: line_num,
-                                start: idx,
-                                length: token_text.len(),
-                                token_type: val_token_type,
-                                item_name,
-                            });
+    fn data_name(&mut self, lexeme: &Lexeme) {
+        let Some((category_name, name)) = split_data_name(lexeme.text) else {
+            // Data name without category.field pattern
+            if self.current_loop.as_ref().is_some_and(|c| c.names_defined) {
+                self.finish_loop();
+            }
+            self.push_token(lexeme, TokenKind::Category, 0, None, None);
+            return;
+        };
+        let (category_name, name) = (category_name.to_string(), name.to_string());
+
+        match self.current_loop.as_mut() {
+            // Item names of a loop header
+            Some(current) if current.is_in_loop_block && !current.names_defined => {
+                if current.category_name.is_empty() {
+                    current.category_name = category_name.clone();
+                } else if current.category_name != category_name {
+                    let next = new_block(lexeme.line, category_name.clone(), true);
+                    if let Some(previous) = self.current_loop.replace(next) {
+                        if !previous.items.is_empty() {
+                            self.loops.push(previous);
                         }
                     }
                 }
-            } else {
-                // Data name without category.field pattern
-                if let Some(idx) = line_text.find(data_name.as_str()) {
-                    tokens.push(Token {
-                        line: line_num,
-                        start: idx,
-                        length: data_name.len(),
-                        token_type: 1,
-                        item_name: None,
-                    });
-                }
             }
-        } else if let Some(ref mut current) = current_loop {
-            // Value lines in loop block
-            if !current.items.is_empty() {
-                if !current.names_defined {
-                    current.names_defined = true;
-                }
+            // Next key-value item of the same category
+            Some(current)
+                if !current.is_in_loop_block && current.category_name == category_name => {}
+            // Anything else ends the current block
+            _ => {
+                self.finish_loop();
+                self.current_loop = Some(new_block(lexeme.line, category_name.clone(), false));
+            }
+        }
 
-                let mut value_ranges: Vec<ValueRange> = Vec::new();
-                let field_count = current.items.len().max(1);
-                let mut search_start = 0usize;
-
-                for (col, (token_text, _)) in line_tokens.iter().enumerate() {
-                    if let Some(rel_idx) = line_text[search_start..].find(token_text.as_str()) {
-                        let idx = search_start + rel_idx;
-                        let current_total = current.processed_value_count + col;
-                        let effective_col_index = current_total % field_count;
-
-                        value_ranges.push(ValueRange {
-                            start: idx,
-                            length: token_text.len(),
-                            column_index: effective_col_index,
-                        });
-
-                        let token_type = 2 + (effective_col_index % 7) as u8;
-                        let item_name = if effective_col_index < current.items.len() {
-                            Some(format!(
-                                "{}.{}",
-                                current.category_name,
-                                current.items[effective_col_index].name
-                            ))
-                        } else {
-                            None
-                        };
-
-                        tokens.push(Token {
-                            line: line_num,
-                            start: idx,
-                            length: token_text.len(),
-                            token_type,
-                            item_name,
-                        });
-
-                        search_start = idx + token_text.len();
-                    }
-                }
+        let current = self.current_loop.as_mut().unwrap();
+        current.items.push(Item {
+            line: lexeme.line,
+            start: lexeme.start + category_name.len() + 1,
+            length: name.len(),
+            name: name.clone(),
+        });
+        let rotation = rotate(current.items.len() - 1, self.options);
+        if !current.is_in_loop_block {
+            self.pending_item = Some(current.items.len() - 1);
+        }
 
-                if !value_ranges.is_empty() {
-                    current.data_lines.push(DataLine {
-                        line: line_num,
-                        value_ranges,
+        // Category part
+        self.tokens.push(Token {
+            line: lexeme.line,
+            start: lexeme.start,
+            length: category_name.len(),
+            kind: TokenKind::Category,
+            rotation: 0,
+            item_name: None,
+            value_kind: None,
+            dimmed: false,
+        });
+        // Dot + Field Name -> rotating color
+        self.tokens.push(Token {
+            line: lexeme.line,
+            start: lexeme.start + category_name.len(),
+            length: 1 + name.len(),
+            kind: TokenKind::ItemName,
+            rotation,
+            item_name: None,
+            value_kind: None,
+            dimmed: false,
+        });
+    }
+
+    fn value(&mut self, lexeme: &Lexeme) {
+        let value_kind = match lexeme.kind {
+            LexemeKind::TextField => ValueKind::TextField,
+            _ => classify_value(lexeme.text, lexeme.kind == LexemeKind::Quoted),
+        };
+
+        // Loop values go round the columns; a key-value is bound to the data name
+        // waiting for it, wherever it appears (same line, next line or text field)
+        let bound = match self.current_loop.as_mut() {
+            Some(current) if current.is_in_loop_block && !current.items.is_empty() => {
+                current.names_defined = true;
+                let col_index = current.processed_value_count % current.items.len();
+                Some((current, col_index))
+            }
+            Some(current) if !current.is_in_loop_block => self
+                .pending_item
+                .take()
+                .map(|col_index| (current, col_index)),
+            _ => None,
+        };
+        let Some((current, col_index)) = bound else {
+            // Value without a data name
+            let kind = value_token_kind(value_kind, self.options);
+            self.push_token(lexeme, kind, 0, None, Some(value_kind));
+            return;
+        };
+        current.processed_value_count += 1;
+        let item_name = Some(format!(
+            "{}.{}",
+            current.category_name, current.items[col_index].name
+        ));
+        let rotation = rotate(col_index, self.options);
+
+        if lexeme.kind == LexemeKind::TextField {
+            // One data line and token per line of the text field
+            for line in lexeme.line..=lexeme.end_line {
+                let length = self.lines.get(line).len();
+                current.data_lines.push(DataLine {
+                    line,
+                    value_ranges: vec![ValueRange {
+                        start: 0,
+                        length,
+                        column_index: col_index,
+                        kind: ValueKind::TextField,
+                    }],
+                });
+                if length > 0 {
+                    self.tokens.push(Token {
+                        line,
+                        start: 0,
+                        length,
+                        kind: TokenKind::TextField,
+                        rotation,
+                        item_name: item_name.clone(),
+                        value_kind: Some(ValueKind::TextField),
+                        dimmed: false,
                     });
-                    current.processed_value_count += line_tokens.len();
                 }
             }
+            return;
         }
-    }
 
-    // Push final loop if exists
-    if let Some(current) = current_loop {
-        if !current.items.is_empty() {
-            loops.push(current);
+        let range = ValueRange {
+            start: lexeme.start,
+            length: lexeme.length,
+            column_index: col_index,
+            kind: value_kind,
+        };
+        match current.data_lines.last_mut() {
+            Some(data_line)
+                if data_line.line == lexeme.line
+                    && data_line.value_ranges[0].kind != ValueKind::TextField =>
+            {
+                data_line.value_ranges.push(range)
+            }
+            _ => current.data_lines.push(DataLine {
+                line: lexeme.line,
+                value_ranges: vec![range],
+            }),
         }
+
+        let kind = value_token_kind(value_kind, self.options);
+        self.push_token(lexeme, kind, rotation, item_name, Some(value_kind));
     }
 
-    ParseResult { loops, tokens }
+    fn push_token(
+        &mut self,
+        lexeme: &Lexeme,
+        kind: TokenKind,
+        rotation: u8,
+        item_name: Option<String>,
+        value_kind: Option<ValueKind>,
+    ) {
+        self.tokens.push(Token {
+            line: lexeme.line,
+            start: lexeme.start,
+            length: lexeme.length,
+            kind,
+            rotation,
+            item_name,
+            value_kind,
+            dimmed: false,
+        });
+    }
 }
 
-/// Helper to get current item name from loop context
-fn get_current_item_name(current_loop: &Option<LoopBlock>) -> Option<String> {
-    if let Some(ref current) = current_loop {
-        if !current.items.is_empty() {
-            let field_count = current.items.len();
-            let col_index = if current.is_in_loop_block {
-                current.processed_value_count % field_count
-            } else {
-                0
-            };
-            if col_index < current.items.len() {
-                return Some(format!(
-                    "{}.{}",
-                    current.category_name, current.items[col_index].name
-                ));
-            }
-        }
+/// Create an empty loop (`is_loop`) or key-value block
+fn new_block(start_line: usize, category_name: String, is_loop: bool) -> LoopBlock {
+    LoopBlock {
+        start_line,
+        category_name,
+        items: Vec::new(),
+        names_defined: !is_loop,
+        is_in_loop_block: is_loop,
+        processed_value_count: 0,
+        data_lines: Vec::new(),
+    }
+}
+
+/// Rotation index for a column or item position
+fn rotate(index: usize, options: &HighlightOptions) -> u8 {
+    (index % options.rotation_length.max(1) as usize) as u8
+}
+
+/// Token kind for a value, optionally distinguishing nulls, numbers and quoted strings
+fn value_token_kind(value_kind: ValueKind, options: &HighlightOptions) -> TokenKind {
+    if options.distinguish_value_kinds {
+        value_kind.token_kind()
+    } else {
+        TokenKind::Value
     }
-    None
 }
 
 #[cfg(test)]
@@ -501,8 +546,190 @@ _atom_site.type_symbol
 1 C
 2 N
 "#;
-        let result = parse_cif_internal(cif);
+        let result = parse_cif_internal(cif, &HighlightOptions::default());
         assert!(!result.loops.is_empty());
         assert!(!result.tokens.is_empty());
     }
+
+    #[test]
+    fn test_token_limit() {
+        let cif = "data_test\nloop_\n_a.x\n_a.y\n1 2\n3 4\n";
+        let options = HighlightOptions::default();
+        // 8 lexemes, but 10 tokens as data names split into category and item
+        assert!(parse_cif_limited(cif, &options, 5).is_none());
+        assert!(parse_cif_limited(cif, &options, 8).is_none());
+        let result = parse_cif_limited(cif, &options, 10).unwrap();
+        assert_eq!(result.tokens.len(), 10);
+    }
+
+    #[test]
+    fn test_token_kinds() {
+        let cif = "data_test\nloop_\n_a.x\n_a.y\n1.5 ?\n'q s' word\n";
+        let options = HighlightOptions {
+            distinguish_value_kinds: true,
+            ..Default::default()
+        };
+        let result = parse_cif_internal(cif, &options);
+        let kinds: Vec<TokenKind> = result.tokens.iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::BlockHeader,
+                TokenKind::LoopKeyword,
+                TokenKind::Category,
+                TokenKind::ItemName,
+                TokenKind::Category,
+                TokenKind::ItemName,
+                TokenKind::Number,
+                TokenKind::Null,
+                TokenKind::QuotedString,
+                TokenKind::Value,
+            ]
+        );
+        let rotations: Vec<u8> = result.tokens[6..].iter().map(|t| t.rotation).collect();
+        assert_eq!(rotations, vec![0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_value_kinds() {
+        let cif = "data_test\nloop_\n_a.x\n_a.y\n1.5(2) .\n\"q s\"\n;\ntext\n;\n";
+        let result = parse_cif_internal(cif, &HighlightOptions::default());
+        let kinds: Vec<ValueKind> = result.tokens.iter().filter_map(|t| t.value_kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ValueKind::FloatWithSu,
+                ValueKind::Inapplicable,
+                ValueKind::DoubleQuoted,
+                ValueKind::TextField,
+                ValueKind::TextField,
+                ValueKind::TextField,
+            ]
+        );
+        // Without distinguish_value_kinds every value stays a plain Value
+        assert!(result
+            .tokens
+            .iter()
+            .filter(|t| t.value_kind.is_some_and(|k| k != ValueKind::TextField))
+            .all(|t| t.kind == TokenKind::Value));
+
+        let ranges = &result.loops[0].data_lines[0].value_ranges;
+        assert_eq!(ranges[0].kind, ValueKind::FloatWithSu);
+    }
+
+    fn item_names(result: &ParseResult) -> Vec<(usize, usize, String)> {
+        result
+            .tokens
+            .iter()
+            .filter_map(|t| Some((t.line, t.start, t.item_name.clone()?)))
+            .collect()
+    }
+
+    #[test]
+    fn test_loop_on_one_line() {
+        let result = parse_cif_internal("loop_ _a.x _a.y 1 2 3 4", &HighlightOptions::default());
+        assert_eq!(result.loops.len(), 1);
+        let block = &result.loops[0];
+        assert_eq!(block.category_name, "_a");
+        assert_eq!(block.items.len(), 2);
+        assert_eq!(block.items[1].start, 14);
+        assert_eq!(block.processed_value_count, 4);
+        assert_eq!(
+            item_names(&result),
+            vec![
+                (0, 16, "_a.x".to_string()),
+                (0, 18, "_a.y".to_string()),
+                (0, 20, "_a.x".to_string()),
+                (0, 22, "_a.y".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_key_values_on_one_line() {
+        let result = parse_cif_internal("_a.x 1 _a.y 'b c'", &HighlightOptions::default());
+        assert_eq!(result.loops.len(), 1);
+        assert!(!result.loops[0].is_in_loop_block);
+        assert_eq!(
+            item_names(&result),
+            vec![(0, 5, "_a.x".to_string()), (0, 12, "_a.y".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_data_name_ends_loop() {
+        let cif = "loop_\n_a.x\n1 2\n_b.y 3\n";
+        let result = parse_cif_internal(cif, &HighlightOptions::default());
+        assert_eq!(result.loops.len(), 2);
+        assert_eq!(result.loops[0].processed_value_count, 2);
+        assert_eq!(result.loops[1].category_name, "_b");
+        assert_eq!(item_names(&result)[2], (3, 5, "_b.y".to_string()));
+    }
+
+    #[test]
+    fn test_key_value_on_following_lines() {
+        let cif = r#"data_test
+_struct.entry_id 1ABC
+_struct.title
+'Crystal structure of a protein'
+_exptl.method
+;X-RAY
+DIFFRACTION
+;
+_citation.id primary
+_citation.title
+;
+Title
+;
+_citation.year 2001
+"#;
+        let result = parse_cif_internal(cif, &HighlightOptions::default());
+        let names = item_names(&result);
+        let name_at = |line: usize| {
+            names
+                .iter()
+                .find(|(l, _, _)| *l == line)
+                .map(|(_, _, n)| n.as_str())
+        };
+        assert_eq!(name_at(1), Some("_struct.entry_id"));
+        assert_eq!(name_at(3), Some("_struct.title"));
+        assert_eq!(name_at(5), Some("_exptl.method"));
+        assert_eq!(name_at(6), Some("_exptl.method"));
+        assert_eq!(name_at(8), Some("_citation.id"));
+        assert_eq!(name_at(10), Some("_citation.title"));
+        assert_eq!(name_at(12), Some("_citation.title"));
+        assert_eq!(name_at(13), Some("_citation.year"));
+
+        let citation = &result.loops[2];
+        assert_eq!(citation.category_name, "_citation");
+        let columns: Vec<usize> = citation
+            .data_lines
+            .iter()
+            .map(|d| d.value_ranges[0].column_index)
+            .collect();
+        assert_eq!(columns, vec![0, 1, 1, 1, 2]);
+    }
+
+    #[test]
+    fn test_extra_key_value_is_unbound() {
+        let result = parse_cif_internal("_a.x 1 2\n_a.y 3", &HighlightOptions::default());
+        let names = item_names(&result);
+        assert_eq!(
+            names,
+            vec![(0, 5, "_a.x".to_string()), (1, 5, "_a.y".to_string())]
+        );
+        assert_eq!(result.loops[0].processed_value_count, 2);
+    }
+
+    #[test]
+    fn test_data_name_characters() {
+        let cif = "_symmetry.space_group_name_H-M 'P 1'\n_cell_length_a 10\n_größe.wert 1\n";
+        let result = parse_cif_internal(cif, &HighlightOptions::default());
+        let names = item_names(&result);
+        assert_eq!(names[0].2, "_symmetry.space_group_name_H-M");
+        // Values follow non-ASCII names at byte offsets; dotless CIF1 names are one token
+        assert_eq!(names[1], (2, 14, "_größe.wert".to_string()));
+        let dotless = result.tokens.iter().find(|t| t.line == 1).unwrap();
+        assert_eq!((dotless.kind, dotless.length), (TokenKind::Category, 14));
+    }
 }
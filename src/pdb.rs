@@ -0,0 +1,150 @@
+//! PDB format writer
+//! Writes CRYST1 and ATOM/HETATM records from `_cell`, `_symmetry` and `_atom_site`
+
+use crate::{Category, DataBlock};
+
+/// Write the coordinates of a data block in PDB format
+pub fn write_pdb(block: &DataBlock) -> String {
+    let mut out = String::new();
+
+    if let Some(cell) = block.category("_cell") {
+        let get = |item: &str| cell.value(item, 0).and_then(|v| v.as_f64());
+        if let (Some(a), Some(b), Some(c)) = (get("length_a"), get("length_b"), get("length_c")) {
+            let space_group = block
+                .value("_symmetry.space_group_name_H-M")
+                .and_then(|v| v.as_str())
+                .unwrap_or("P 1");
+            out.push_str(&format!(
+                "CRYST1{:>9.3}{:>9.3}{:>9.3}{:>7.2}{:>7.2}{:>7.2} {:<11}\n",
+                a,
+                b,
+                c,
+                get("angle_alpha").unwrap_or(90.0),
+                get("angle_beta").unwrap_or(90.0),
+                get("angle_gamma").unwrap_or(90.0),
+                space_group
+            ));
+        }
+    }
+
+    let Some(atom_site) = block.category("_atom_site") else {
+        out.push_str("END\n");
+        return out;
+    };
+
+    let mut model: Option<String> = None;
+    for row in 0..atom_site.row_count() {
+        let current_model = text(atom_site, row, &["pdbx_PDB_model_num"]);
+        if !current_model.is_empty() && model.as_deref() != Some(current_model) {
+            if model.is_some() {
+                out.push_str("ENDMDL\n");
+            }
+            out.push_str(&format!("MODEL     {:>4}\n", current_model));
+            model = Some(current_model.to_string());
+        }
+        out.push_str(&atom_record(atom_site, row));
+        out.push('\n');
+    }
+    if model.is_some() {
+        out.push_str("ENDMDL\n");
+    }
+    out.push_str("END\n");
+    out
+}
+
+/// Format one ATOM/HETATM record
+fn atom_record(atom_site: &Category, row: usize) -> String {
+    let group = match text(atom_site, row, &["group_PDB"]) {
+        "HETATM" => "HETATM",
+        _ => "ATOM",
+    };
+    let element = text(atom_site, row, &["type_symbol"]);
+    let atom_name = text(atom_site, row, &["auth_atom_id", "label_atom_id"]);
+    // Atom names shorter than 4 characters with 1-letter elements start in column 14
+    let atom_name = if atom_name.len() < 4 && element.len() == 1 {
+        format!(" {}", atom_name)
+    } else {
+        atom_name.to_string()
+    };
+    let number = |items: &[&str]| {
+        items
+            .iter()
+            .find_map(|item| atom_site.value(item, row)?.as_f64())
+            .unwrap_or(0.0)
+    };
+    let serial = text(atom_site, row, &["id"]);
+    let serial = serial.get(serial.len().saturating_sub(5)..).unwrap_or("");
+
+    format!(
+        "{:<6}{:>5} {:<4}{:1}{:>3} {:1}{:>4}{:1}   {:>8.3}{:>8.3}{:>8.3}{:>6.2}{:>6.2}          {:>2}",
+        group,
+        serial,
+        atom_name,
+        text(atom_site, row, &["label_alt_id"]),
+        text(atom_site, row, &["auth_comp_id", "label_comp_id"]),
+        text(atom_site, row, &["auth_asym_id", "label_asym_id"]),
+        text(atom_site, row, &["auth_seq_id", "label_seq_id"]),
+        text(atom_site, row, &["pdbx_PDB_ins_code"]),
+        number(&["Cartn_x"]),
+        number(&["Cartn_y"]),
+        number(&["Cartn_z"]),
+        number(&["occupancy"]),
+        number(&["B_iso_or_equiv"]),
+        element.to_uppercase(),
+    )
+}
+
+/// First non-null value among `items`, or an empty string
+fn text<'a>(category: &'a Category, row: usize, items: &[&str]) -> &'a str {
+    items
+        .iter()
+        .find_map(|item| category.value(item, row)?.as_str())
+        .unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Document;
+
+    #[test]
+    fn test_atom_records() {
+        let cif = r#"data_x
+_cell.length_a 10
+_cell.length_b 20
+_cell.length_c 30
+loop_
+_atom_site.group_PDB
+_atom_site.id
+_atom_site.type_symbol
+_atom_site.label_atom_id
+_atom_site.label_alt_id
+_atom_site.label_comp_id
+_atom_site.label_asym_id
+_atom_site.label_seq_id
+_atom_site.Cartn_x
+_atom_site.Cartn_y
+_atom_site.Cartn_z
+_atom_site.occupancy
+_atom_site.B_iso_or_equiv
+ATOM 1 N N . MET A 1 1.0 2.0 3.0 1.00 20.5
+HETATM 2 ZN ZN . ZN B . -1.5 0 10 0.5 30
+"#;
+        let doc = Document::parse(cif);
+        let pdb = write_pdb(doc.first_block().unwrap());
+        let lines: Vec<&str> = pdb.lines().collect();
+        assert_eq!(
+            lines[0],
+            "CRYST1   10.000   20.000   30.000  90.00  90.00  90.00 P 1        "
+        );
+        assert_eq!(
+            lines[1],
+            "ATOM      1  N   MET A   1       1.000   2.000   3.000  1.00 20.50           N"
+        );
+        assert_eq!(
+            lines[2],
+            "HETATM    2 ZN    ZN B          -1.500   0.000  10.000  0.50 30.00          ZN"
+        );
+        assert_eq!(lines[3], "END");
+    }
+}
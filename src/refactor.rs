@@ -0,0 +1,500 @@
+//! Consistent edits across categories: chain renames, residue renumbering, category renames
+//! Operations return text edits of the affected values only, so the rest of the file keeps its layout
+
+use crate::parser::parse_cif_internal;
+use crate::relations::{item_at, token_at};
+use crate::semantic::{line_of, utf16_column};
+use crate::tokenizer::{split_data_name, unquote_value};
+use crate::writer::format_value;
+use crate::{HighlightOptions, LoopBlock, ParseResult, Value, ValueKind, ValueRange};
+use memchr::memchr_iter;
+use serde::Serialize;
+use std::fmt;
+use tsify::Tsify;
+
+/// Replacement from byte column `start` of `line` to byte column `end` of `end_line`.
+/// Most edits stay on one line; those across a line break end on a later one.
+#[derive(Serialize, Tsify, Clone, Debug, PartialEq, Eq)]
+pub struct TextEdit {
+    pub line: usize,
+    pub start: usize,
+    pub end_line: usize,
+    pub end: usize,
+    pub new_text: String,
+}
+
+/// Which chain identifier of a structure to rename
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChainId {
+    /// `label_asym_id`, `_struct_asym.id` and the `asym_id` columns of other categories
+    Label,
+    /// `auth_asym_id` and the `pdb_strand_id` / `pdbx_strand_id` columns
+    Auth,
+}
+
+/// Error of an edit operation; no edits are made
+#[derive(Debug, PartialEq, Eq)]
+pub enum EditError {
+    /// New value is empty or cannot be written in place (line breaks, commas,
+    /// or both quote characters followed by spaces)
+    InvalidValue(String),
+    /// New category name is not a valid `_category` name
+    InvalidCategory(String),
+    /// The new chain ID or category name is already used in the document
+    AlreadyExists(String),
+    /// A renumbered value would not fit in a 64-bit integer
+    OutOfRange(String),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditError::InvalidValue(value) => write!(f, "invalid value '{}'", value),
+            EditError::InvalidCategory(name) => write!(f, "invalid category name '{}'", name),
+            EditError::AlreadyExists(name) => write!(f, "'{}' already exists", name),
+            EditError::OutOfRange(value) => {
+                write!(f, "'{}' plus the offset is out of range", value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+/// Edits renaming chain `old` to `new` in every chain ID column of every category,
+/// including comma-separated lists such as `_pdbx_struct_assembly_gen.asym_id_list`.
+/// Fails if a chain ID column already holds `new`.
+pub fn rename_chain(
+    text: &str,
+    chain: ChainId,
+    old: &str,
+    new: &str,
+) -> Result<Vec<TextEdit>, EditError> {
+    let result = parse_cif_internal(text, &HighlightOptions::default());
+    let lines: Vec<&str> = text.lines().collect();
+    let line_text = line_of(&lines);
+    rename_chain_with(&result, &line_text, chain, old, new)
+}
+
+/// `rename_chain` on an existing parse result, with `line_text` giving the text of a line
+pub(crate) fn rename_chain_with<'t>(
+    result: &ParseResult,
+    line_text: &dyn Fn(usize) -> &'t str,
+    chain: ChainId,
+    old: &str,
+    new: &str,
+) -> Result<Vec<TextEdit>, EditError> {
+    // A value that can only be written as a text field would span lines
+    if new.is_empty() || new.contains(',') || format_value(&Value::from_text(new)).contains('\n') {
+        return Err(EditError::InvalidValue(new.to_string()));
+    }
+    if old == new {
+        return Ok(Vec::new());
+    }
+    let mut edits = Vec::new();
+    for block in &result.loops {
+        let cells = cells(block);
+        for (column, item) in block.items.iter().enumerate() {
+            if !is_chain_item(&block.category_name, &item.name, chain) {
+                continue;
+            }
+            for cell in cells.iter().filter(|c| c.range.column_index == column) {
+                let Some(value) = cell.value(line_text) else {
+                    continue;
+                };
+                let members: Vec<&str> = value.split(',').collect();
+                if members.contains(&new) {
+                    return Err(EditError::AlreadyExists(new.to_string()));
+                }
+                if members.contains(&old) {
+                    let renamed: Vec<&str> = members
+                        .iter()
+                        .map(|&m| if m == old { new } else { m })
+                        .collect();
+                    edits.push(cell.edit(&renamed.join(",")));
+                }
+            }
+        }
+    }
+    edits.sort_by_key(|e| (e.line, e.start));
+    Ok(edits)
+}
+
+/// Edits adding `offset` to the author residue numbers (`auth_seq_id`, `pdb_seq_num`, ...)
+/// of every category, limited to rows of author chain `chain` if given.
+/// Fails if a renumbered value would overflow.
+pub fn renumber_residues(
+    text: &str,
+    chain: Option<&str>,
+    offset: i64,
+) -> Result<Vec<TextEdit>, EditError> {
+    let result = parse_cif_internal(text, &HighlightOptions::default());
+    let lines: Vec<&str> = text.lines().collect();
+    let line_text = line_of(&lines);
+    renumber_residues_with(&result, &line_text, chain, offset)
+}
+
+/// `renumber_residues` on an existing parse result, with `line_text` giving the text of a line
+pub(crate) fn renumber_residues_with<'t>(
+    result: &ParseResult,
+    line_text: &dyn Fn(usize) -> &'t str,
+    chain: Option<&str>,
+    offset: i64,
+) -> Result<Vec<TextEdit>, EditError> {
+    let mut edits = Vec::new();
+    for block in &result.loops {
+        let cells = cells(block);
+        for (column, item) in block.items.iter().enumerate() {
+            let Some(chain_items) = residue_chain_items(&item.name) else {
+                continue;
+            };
+            // Author chain of each row, for rows limited to `chain`
+            let chain_column = chain_items.iter().find_map(|name| {
+                block
+                    .items
+                    .iter()
+                    .position(|i| i.name.eq_ignore_ascii_case(name))
+            });
+            let row_chains: Vec<Option<&str>> = match chain_column {
+                Some(chain_column) => cells
+                    .iter()
+                    .filter(|c| c.range.column_index == chain_column)
+                    .map(|c| c.value(line_text))
+                    .collect(),
+                None if chain.is_some() => continue,
+                None => Vec::new(),
+            };
+            for cell in cells.iter().filter(|c| c.range.column_index == column) {
+                let in_chain = chain
+                    .is_none_or(|chain| row_chains.get(cell.row).copied().flatten() == Some(chain));
+                let Some(value) = cell.value(line_text).filter(|_| in_chain) else {
+                    continue;
+                };
+                if let Ok(number) = value.parse::<i64>() {
+                    let renumbered = number
+                        .checked_add(offset)
+                        .ok_or_else(|| EditError::OutOfRange(value.to_string()))?;
+                    edits.push(cell.edit(&renumbered.to_string()));
+                }
+            }
+        }
+    }
+    edits.sort_by_key(|e| (e.line, e.start));
+    Ok(edits)
+}
+
+/// Edits renaming category `old` to `new` in all its data names (`_old.x` to `_new.x`).
+/// Fails if a category named `new` already exists.
+pub fn rename_category(text: &str, old: &str, new: &str) -> Result<Vec<TextEdit>, EditError> {
+    let result = parse_cif_internal(text, &HighlightOptions::default());
+    rename_category_with(&result, old, new)
+}
+
+/// `rename_category` on an existing parse result
+pub(crate) fn rename_category_with(
+    result: &ParseResult,
+    old: &str,
+    new: &str,
+) -> Result<Vec<TextEdit>, EditError> {
+    let valid = new.len() > 1
+        && new.starts_with('_')
+        && !new.contains(|c: char| c == '.' || c.is_whitespace());
+    if !valid {
+        return Err(EditError::InvalidCategory(new.to_string()));
+    }
+    if !old.eq_ignore_ascii_case(new)
+        && result
+            .loops
+            .iter()
+            .any(|l| l.category_name.eq_ignore_ascii_case(new))
+    {
+        return Err(EditError::AlreadyExists(new.to_string()));
+    }
+    let mut edits: Vec<TextEdit> = result
+        .loops
+        .iter()
+        .filter(|l| l.category_name.eq_ignore_ascii_case(old))
+        .flat_map(|l| {
+            l.items.iter().map(|item| TextEdit {
+                line: item.line,
+                start: item.start - l.category_name.len() - 1,
+                end_line: item.line,
+                end: item.start - 1,
+                new_text: new.to_string(),
+            })
+        })
+        .collect();
+    edits.sort_by_key(|e| (e.line, e.start));
+    Ok(edits)
+}
+
+/// Edits renaming what is at `line` and byte `column`: the category of a data name,
+/// or the chain of a label or author chain ID value. Empty if there is nothing to rename.
+pub fn rename_at(
+    text: &str,
+    line: usize,
+    column: usize,
+    new: &str,
+) -> Result<Vec<TextEdit>, EditError> {
+    let result = parse_cif_internal(text, &HighlightOptions::default());
+    let lines: Vec<&str> = text.lines().collect();
+    let line_text = line_of(&lines);
+    rename_at_with(&result, &line_text, line, column, new)
+}
+
+/// `rename_at` on an existing parse result, with `line_text` giving the text of a line
+pub(crate) fn rename_at_with<'t>(
+    result: &ParseResult,
+    line_text: &dyn Fn(usize) -> &'t str,
+    line: usize,
+    column: usize,
+    new: &str,
+) -> Result<Vec<TextEdit>, EditError> {
+    if let Some((block, _)) = item_at(&result.loops, line, column) {
+        return rename_category_with(result, &block.category_name, new);
+    }
+    let Some(token) = token_at(&result.tokens, line, column) else {
+        return Ok(Vec::new());
+    };
+    let data_name = token.item_name.as_deref().and_then(split_data_name);
+    let (Some((category, item)), Some(kind)) = (data_name, token.value_kind) else {
+        return Ok(Vec::new());
+    };
+    let raw = line_text(line)
+        .get(token.start..token.start + token.length)
+        .unwrap_or("");
+    let old = unquote_value(kind, raw);
+    match [ChainId::Label, ChainId::Auth]
+        .into_iter()
+        .find(|&chain| is_chain_item(category, item, chain))
+    {
+        Some(chain) if !kind.is_null() && kind != ValueKind::TextField => {
+            rename_chain_with(result, line_text, chain, old, new)
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Edits with columns converted from bytes to UTF-16 code units of the lines given by `line_text`
+pub(crate) fn utf16_edits<'t>(
+    edits: Vec<TextEdit>,
+    line_text: &dyn Fn(usize) -> &'t str,
+) -> Vec<TextEdit> {
+    edits
+        .into_iter()
+        .map(|edit| TextEdit {
+            start: utf16_column(line_text(edit.line), edit.start),
+            end: utf16_column(line_text(edit.end_line), edit.end),
+            ..edit
+        })
+        .collect()
+}
+
+/// Apply non-overlapping edits to `text`
+pub fn apply_edits(text: &str, edits: &[TextEdit]) -> String {
+    let mut line_starts = vec![0];
+    line_starts.extend(memchr_iter(b'\n', text.as_bytes()).map(|n| n + 1));
+    let mut edits: Vec<&TextEdit> = edits.iter().collect();
+    edits.sort_by_key(|e| (e.line, e.start));
+    let mut output = text.to_string();
+    for edit in edits.iter().rev() {
+        let (Some(&line_start), Some(&end_line_start)) =
+            (line_starts.get(edit.line), line_starts.get(edit.end_line))
+        else {
+            continue;
+        };
+        let start = (line_start + edit.start).min(text.len());
+        let end = (end_line_start + edit.end).min(text.len()).max(start);
+        output.replace_range(start..end, &edit.new_text);
+    }
+    output
+}
+
+/// Whether an item holds chain IDs of the given kind
+fn is_chain_item(category: &str, item: &str, chain: ChainId) -> bool {
+    let item = item.to_ascii_lowercase();
+    match chain {
+        ChainId::Label => {
+            item.contains("label_asym_id")
+                || item == "asym_id"
+                || item == "asym_id_list"
+                || (category.eq_ignore_ascii_case("_struct_asym") && item == "id")
+        }
+        ChainId::Auth => {
+            item.contains("auth_asym_id")
+                || ["pdb_strand_id", "pdbx_strand_id", "pdb_asym_id"].contains(&item.as_str())
+        }
+    }
+}
+
+/// Candidate author chain items of the same row, if `item` is an author residue number
+fn residue_chain_items(item: &str) -> Option<Vec<String>> {
+    let item = item.to_ascii_lowercase();
+    if item.contains("auth_seq_id") {
+        // `beg_auth_seq_id` pairs with `beg_auth_asym_id`, `auth_seq_id_1` with `auth_asym_id_1`
+        return Some(vec![item.replace("auth_seq_id", "auth_asym_id")]);
+    }
+    (item == "pdb_seq_num" || item == "auth_seq_num").then(|| {
+        vec![
+            "pdb_strand_id".to_string(),
+            "pdb_asym_id".to_string(),
+            "auth_asym_id".to_string(),
+        ]
+    })
+}
+
+/// A value of a loop or key-value block with its row
+struct Cell<'a> {
+    line: usize,
+    range: &'a ValueRange,
+    row: usize,
+}
+
+impl Cell<'_> {
+    /// Unquoted text of a non-null value outside text fields
+    fn value<'t>(&self, line_text: &dyn Fn(usize) -> &'t str) -> Option<&'t str> {
+        if self.range.kind == ValueKind::TextField || self.range.kind.is_null() {
+            return None;
+        }
+        let raw =
+            line_text(self.line).get(self.range.start..self.range.start + self.range.length)?;
+        Some(unquote_value(self.range.kind, raw))
+    }
+
+    /// Edit replacing the value with `text`, keeping its quotes
+    fn edit(&self, text: &str) -> TextEdit {
+        let kind = match self.range.kind {
+            kind @ (ValueKind::SingleQuoted | ValueKind::DoubleQuoted) => kind,
+            _ => Value::from_text(text).kind,
+        };
+        TextEdit {
+            line: self.line,
+            start: self.range.start,
+            end_line: self.line,
+            end: self.range.start + self.range.length,
+            new_text: format_value(&Value {
+                text: text.to_string(),
+                kind,
+            }),
+        }
+    }
+}
+
+/// Values of a block in order, one per value (text fields by their first line)
+fn cells(block: &LoopBlock) -> Vec<Cell<'_>> {
+    let width = block.items.len().max(1);
+    let mut cells: Vec<Cell> = Vec::new();
+    let mut count = 0;
+    for data_line in &block.data_lines {
+        for range in &data_line.value_ranges {
+            // Later lines of a text field continue its value
+            let continues = range.kind == ValueKind::TextField
+                && cells.last().is_some_and(|last| {
+                    last.range.kind == ValueKind::TextField
+                        && last.range.column_index == range.column_index
+                        && last.line < data_line.line
+                });
+            if continues {
+                continue;
+            }
+            let row = if block.is_in_loop_block {
+                count / width
+            } else {
+                0
+            };
+            cells.push(Cell {
+                line: data_line.line,
+                range,
+                row,
+            });
+            count += 1;
+        }
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CIF: &str = "data_1ABC\n\
+loop_\n_struct_asym.id\n_struct_asym.entity_id\nA 1\nB 2\n\
+_pdbx_struct_assembly_gen.asym_id_list A,B\n\
+_struct_conn.ptnr1_label_asym_id 'A'\n_struct_conn.ptnr1_auth_asym_id  'A'\n\
+_struct_conn.ptnr1_auth_seq_id   10\n\
+loop_\n_atom_site.id\n_atom_site.label_asym_id\n_atom_site.auth_asym_id\n_atom_site.auth_seq_id\n\
+_atom_site.note\n\
+1 A A 10 .\n2 A A 11\n;\nnote\n;\n3 B B 1 ?\n";
+
+    #[test]
+    fn test_rename_chain() {
+        let edits = rename_chain(CIF, ChainId::Label, "A", "C").unwrap();
+        let renamed = apply_edits(CIF, &edits);
+        assert!(renamed.contains("\nC 1\nB 2\n"));
+        assert!(renamed.contains("asym_id_list C,B\n"));
+        assert!(renamed.contains("_struct_conn.ptnr1_label_asym_id 'C'\n"));
+        assert!(renamed.contains("_struct_conn.ptnr1_auth_asym_id  'A'\n"));
+        assert!(renamed.contains("\n1 C A 10 .\n2 C A 11\n"));
+        assert_eq!(edits.len(), 5);
+
+        let edits = rename_chain(CIF, ChainId::Auth, "A", "chain X").unwrap();
+        let renamed = apply_edits(CIF, &edits);
+        assert!(renamed.contains("ptnr1_auth_asym_id  'chain X'\n"));
+        assert!(renamed.contains("\n1 A 'chain X' 10 .\n"));
+
+        assert_eq!(
+            rename_chain(CIF, ChainId::Label, "A", "B"),
+            Err(EditError::AlreadyExists("B".to_string()))
+        );
+        assert!(rename_chain(CIF, ChainId::Label, "A", "C,D").is_err());
+        assert_eq!(
+            rename_chain(CIF, ChainId::Label, "A", "x' y\""),
+            Err(EditError::InvalidValue("x' y\"".to_string()))
+        );
+        assert!(rename_chain(CIF, ChainId::Label, "A", "x'").is_ok());
+    }
+
+    #[test]
+    fn test_renumber_residues() {
+        let renumbered = apply_edits(CIF, &renumber_residues(CIF, Some("A"), 100).unwrap());
+        assert!(renumbered.contains("ptnr1_auth_seq_id   110\n"));
+        assert!(renumbered.contains("\n1 A A 110 .\n2 A A 111\n"));
+        assert!(renumbered.contains("\n3 B B 1 ?\n"));
+
+        let all = apply_edits(CIF, &renumber_residues(CIF, None, -1).unwrap());
+        assert!(all.contains("\n3 B B 0 ?\n"));
+        assert!(renumber_residues(CIF, Some("Z"), 1).unwrap().is_empty());
+        assert!(matches!(
+            renumber_residues(CIF, None, i64::MAX),
+            Err(EditError::OutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn test_rename_at() {
+        // On `ptnr1_auth_asym_id 'A'`: author chain, not the label chain
+        let edits = rename_at(CIF, 8, 34, "Z").unwrap();
+        assert_eq!(edits.len(), 3);
+        assert_eq!(apply_edits(CIF, &edits).matches("A Z").count(), 2);
+        // On `_atom_site` of a data name
+        let edits = rename_at(CIF, 13, 2, "_atom").unwrap();
+        assert_eq!(edits.len(), 5);
+        // On an atom id
+        assert!(rename_at(CIF, 17, 0, "Z").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rename_category() {
+        let text = "data_x\n_a.x 1\n_a.y 2\nloop_\n_A.z\n3\n_b.w 4\n";
+        let edits = rename_category(text, "_a", "_new").unwrap();
+        assert_eq!(
+            apply_edits(text, &edits),
+            "data_x\n_new.x 1\n_new.y 2\nloop_\n_new.z\n3\n_b.w 4\n"
+        );
+        assert_eq!(
+            rename_category(text, "_a", "_b"),
+            Err(EditError::AlreadyExists("_b".to_string()))
+        );
+        assert!(rename_category(text, "_a", "a.b").is_err());
+    }
+}
@@ -0,0 +1,368 @@
+//! Parent/child relations between data names and go-to-definition along them
+//! Jumps from a child value (`_atom_site.label_entity_id 2`) to the parent row (`_entity.id 2`)
+
+use crate::parser::parse_cif_internal;
+use crate::semantic::line_of;
+use crate::tokenizer::{split_data_name, unquote_value};
+use crate::{
+    Document, HighlightOptions, Item, LoopBlock, ParseResult, Token, TokenKind, ValueKind,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use tsify::Tsify;
+
+/// Common parent/child links of the PDBx/mmCIF dictionary as (child, parent)
+const MMCIF_LINKS: &[(&str, &str)] = &[
+    ("_atom_site.label_entity_id", "_entity.id"),
+    ("_atom_site.label_asym_id", "_struct_asym.id"),
+    ("_atom_site.label_comp_id", "_chem_comp.id"),
+    ("_atom_site.type_symbol", "_atom_type.symbol"),
+    ("_atom_site_anisotrop.id", "_atom_site.id"),
+    ("_chem_comp_atom.comp_id", "_chem_comp.id"),
+    ("_chem_comp_bond.comp_id", "_chem_comp.id"),
+    ("_entity_poly.entity_id", "_entity.id"),
+    ("_entity_poly_seq.entity_id", "_entity.id"),
+    ("_entity_poly_seq.mon_id", "_chem_comp.id"),
+    ("_entity_src_gen.entity_id", "_entity.id"),
+    ("_entity_src_nat.entity_id", "_entity.id"),
+    ("_pdbx_entity_src_syn.entity_id", "_entity.id"),
+    ("_pdbx_entity_nonpoly.entity_id", "_entity.id"),
+    ("_pdbx_entity_nonpoly.comp_id", "_chem_comp.id"),
+    ("_struct_asym.entity_id", "_entity.id"),
+    ("_pdbx_poly_seq_scheme.asym_id", "_struct_asym.id"),
+    ("_pdbx_poly_seq_scheme.entity_id", "_entity.id"),
+    ("_pdbx_poly_seq_scheme.mon_id", "_chem_comp.id"),
+    ("_pdbx_nonpoly_scheme.asym_id", "_struct_asym.id"),
+    ("_pdbx_nonpoly_scheme.entity_id", "_entity.id"),
+    ("_pdbx_nonpoly_scheme.mon_id", "_chem_comp.id"),
+    ("_struct_conn.conn_type_id", "_struct_conn_type.id"),
+    ("_struct_conn.ptnr1_label_asym_id", "_struct_asym.id"),
+    ("_struct_conn.ptnr2_label_asym_id", "_struct_asym.id"),
+    ("_struct_conn.ptnr1_label_comp_id", "_chem_comp.id"),
+    ("_struct_conn.ptnr2_label_comp_id", "_chem_comp.id"),
+    ("_struct_conf.conf_type_id", "_struct_conf_type.id"),
+    ("_struct_conf.beg_label_asym_id", "_struct_asym.id"),
+    ("_struct_conf.end_label_asym_id", "_struct_asym.id"),
+    ("_struct_conf.beg_label_comp_id", "_chem_comp.id"),
+    ("_struct_conf.end_label_comp_id", "_chem_comp.id"),
+    ("_struct_sheet_range.sheet_id", "_struct_sheet.id"),
+    ("_struct_sheet_range.beg_label_asym_id", "_struct_asym.id"),
+    ("_struct_sheet_range.end_label_asym_id", "_struct_asym.id"),
+    ("_struct_sheet_order.sheet_id", "_struct_sheet.id"),
+    ("_struct_site_gen.site_id", "_struct_site.id"),
+    ("_struct_site_gen.label_asym_id", "_struct_asym.id"),
+    ("_struct_site_gen.label_comp_id", "_chem_comp.id"),
+    ("_struct_ref.entity_id", "_entity.id"),
+    ("_struct_ref_seq.ref_id", "_struct_ref.id"),
+    ("_struct_ref_seq_dif.align_id", "_struct_ref_seq.align_id"),
+    (
+        "_pdbx_struct_assembly_gen.assembly_id",
+        "_pdbx_struct_assembly.id",
+    ),
+    (
+        "_pdbx_struct_assembly_prop.biol_id",
+        "_pdbx_struct_assembly.id",
+    ),
+    ("_refine_hist.pdbx_refine_id", "_refine.pdbx_refine_id"),
+    ("_refine_ls_shell.pdbx_refine_id", "_refine.pdbx_refine_id"),
+    ("_reflns_shell.pdbx_ordinal", "_reflns.pdbx_ordinal"),
+];
+
+/// Parent/child links between data names, matched ignoring ASCII case.
+/// Only single-item keys are followed: a child of a composite key (e.g.
+/// `_atom_site.label_seq_id` of `_entity_poly_seq`) jumps to the first row with its value.
+#[derive(Clone, Debug, Default)]
+pub struct Relations {
+    /// Parent data names by lowercase child data name
+    parents: HashMap<String, Vec<String>>,
+    /// Child data names by lowercase parent data name
+    children: HashMap<String, Vec<String>>,
+}
+
+impl Relations {
+    /// Built-in links of the PDBx/mmCIF dictionary between the common categories
+    pub fn mmcif() -> Self {
+        let mut relations = Relations::default();
+        for (child, parent) in MMCIF_LINKS {
+            relations.insert(child, parent);
+        }
+        relations
+    }
+
+    /// Links defined by a dictionary: `_item_linked` in DDL2 save frames
+    /// and `_name.linked_item_id` in DDLm definitions
+    pub fn from_dictionary(dictionary: &Document) -> Self {
+        let mut relations = Relations::default();
+        let frames = dictionary
+            .blocks
+            .iter()
+            .flat_map(|b| std::iter::once(b).chain(&b.frames));
+        for frame in frames {
+            if let Some(linked) = frame.category("_item_linked") {
+                let pairs = linked
+                    .column("child_name")
+                    .into_iter()
+                    .flatten()
+                    .zip(linked.column("parent_name").into_iter().flatten());
+                for (child, parent) in pairs {
+                    if let (Some(child), Some(parent)) = (child.as_str(), parent.as_str()) {
+                        relations.insert(child, parent);
+                    }
+                }
+            }
+            let child = frame.value("_definition.id").and_then(|v| v.as_str());
+            let parent = frame.value("_name.linked_item_id").and_then(|v| v.as_str());
+            if let (Some(child), Some(parent)) = (child, parent) {
+                relations.insert(child, parent);
+            }
+        }
+        relations
+    }
+
+    /// Add a link from `child` to `parent` (`_atom_site.label_entity_id`, `_entity.id`)
+    pub fn insert(&mut self, child: &str, parent: &str) {
+        let parents = self.parents.entry(child.to_ascii_lowercase()).or_default();
+        if !parents.iter().any(|p| p.eq_ignore_ascii_case(parent)) {
+            parents.push(parent.to_string());
+            let children = self
+                .children
+                .entry(parent.to_ascii_lowercase())
+                .or_default();
+            children.push(child.to_string());
+        }
+    }
+
+    /// Parent data names of `child`
+    pub fn parents(&self, child: &str) -> &[String] {
+        self.parents
+            .get(&child.to_ascii_lowercase())
+            .map_or(&[], |p| p.as_slice())
+    }
+
+    /// Child data names of `parent`
+    pub fn children(&self, parent: &str) -> &[String] {
+        self.children
+            .get(&parent.to_ascii_lowercase())
+            .map_or(&[], |c| c.as_slice())
+    }
+}
+
+/// Location of a parent value or data name
+#[derive(Serialize, Tsify, Clone, Debug, PartialEq, Eq)]
+pub struct Definition {
+    /// Parent data name (`_entity.id`)
+    pub data_name: String,
+    pub line: usize,
+    pub start: usize,
+    pub length: usize,
+}
+
+/// Definition of the child value or data name at `line` and byte `column` of `text`,
+/// searched in the same data block. On a value this is the parent row's key value;
+/// on a data name it is the parent data name.
+pub fn definition(
+    text: &str,
+    line: usize,
+    column: usize,
+    relations: &Relations,
+) -> Option<Definition> {
+    let result = parse_cif_internal(text, &HighlightOptions::default());
+    let lines: Vec<&str> = text.lines().collect();
+    let line_text = line_of(&lines);
+    definition_with(&result, &line_text, line, column, relations)
+}
+
+/// `definition` on an existing parse result, with `line_text` giving the text of a line
+pub(crate) fn definition_with<'t>(
+    result: &ParseResult,
+    line_text: &dyn Fn(usize) -> &'t str,
+    line: usize,
+    column: usize,
+    relations: &Relations,
+) -> Option<Definition> {
+    let block_starts: Vec<usize> = result
+        .tokens
+        .iter()
+        .filter(|t| t.kind == TokenKind::BlockHeader)
+        .filter(|t| line_text(t.line)[t.start..].starts_with("data_"))
+        .map(|t| t.line)
+        .collect();
+    let block_of = |line: usize| block_starts.partition_point(|&start| start <= line);
+    let block = block_of(line);
+    let block_loops: Vec<&LoopBlock> = result
+        .loops
+        .iter()
+        .filter(|l| block_of(l.start_line) == block)
+        .collect();
+    let columns = |data_name: &str| item_columns(&block_loops, data_name);
+
+    if let Some((loop_block, item)) = item_at(&result.loops, line, column) {
+        let child = format!("{}.{}", loop_block.category_name, item.name);
+        return relations.parents(&child).iter().find_map(|parent| {
+            let (parent_loop, column) = columns(parent).into_iter().next()?;
+            let item = &parent_loop.items[column];
+            let category_length = parent_loop.category_name.len() + 1;
+            Some(Definition {
+                data_name: parent.clone(),
+                line: item.line,
+                start: item.start - category_length,
+                length: category_length + item.length,
+            })
+        });
+    }
+
+    let token = token_at(&result.tokens, line, column)?;
+    let value_kind = token.value_kind.filter(|&k| k != ValueKind::TextField)?;
+    let raw = line_text(line).get(token.start..token.start + token.length)?;
+    let value = unquote_value(value_kind, raw);
+    let child = token.item_name.as_deref()?;
+    relations.parents(child).iter().find_map(|parent| {
+        columns(parent)
+            .into_iter()
+            .find_map(|(parent_loop, column)| {
+                parent_loop.data_lines.iter().find_map(|data_line| {
+                    data_line.value_ranges.iter().find_map(|range| {
+                        let raw = line_text(data_line.line)
+                            .get(range.start..range.start + range.length)?;
+                        (range.column_index == column
+                            && range.kind != ValueKind::TextField
+                            && unquote_value(range.kind, raw) == value)
+                            .then(|| Definition {
+                                data_name: parent.clone(),
+                                line: data_line.line,
+                                start: range.start,
+                                length: range.length,
+                            })
+                    })
+                })
+            })
+    })
+}
+
+/// Loops holding `data_name` with the column index of its item
+fn item_columns<'a>(loops: &[&'a LoopBlock], data_name: &str) -> Vec<(&'a LoopBlock, usize)> {
+    let (category, item) = split_data_name(data_name).unwrap_or(("", data_name));
+    loops
+        .iter()
+        .filter(|l| l.category_name.eq_ignore_ascii_case(category))
+        .filter_map(|l| {
+            let column = l
+                .items
+                .iter()
+                .position(|i| i.name.eq_ignore_ascii_case(item))?;
+            Some((*l, column))
+        })
+        .collect()
+}
+
+/// Loop and item whose data name covers `column` of `line`
+pub(crate) fn item_at(
+    loops: &[LoopBlock],
+    line: usize,
+    column: usize,
+) -> Option<(&LoopBlock, &Item)> {
+    loops.iter().find_map(|l| {
+        let category_length = l.category_name.len() + 1;
+        let item = l.items.iter().find(|i| {
+            i.line == line && i.start - category_length <= column && column <= i.start + i.length
+        })?;
+        Some((l, item))
+    })
+}
+
+/// Token covering `column` of `line`, or ending there (a cursor just after a word)
+pub(crate) fn token_at(tokens: &[Token], line: usize, column: usize) -> Option<&Token> {
+    let on_line = tokens.iter().filter(|t| t.line == line);
+    on_line
+        .clone()
+        .find(|t| t.start <= column && column < t.start + t.length)
+        .or_else(|| on_line.clone().find(|t| t.start + t.length == column))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CIF: &str = "data_1ABC\n\
+loop_\n_entity.id\n_entity.type\n1 polymer\n2 non-polymer\n\
+loop_\n_struct_asym.id\n_struct_asym.entity_id\nA 1\nB 2\n\
+_struct_conn.id covale1\n_struct_conn.ptnr1_label_asym_id 'B'\n\
+loop_\n_atom_site.id\n_atom_site.label_entity_id\n_atom_site.label_asym_id\n1 1 A\n2 2 B\n\
+data_2XYZ\n_entity.id 2\n";
+
+    fn target(definition: Option<Definition>) -> Option<(String, usize, usize)> {
+        definition.map(|d| (d.data_name, d.line, d.start))
+    }
+
+    #[test]
+    fn test_value_definition() {
+        let relations = Relations::mmcif();
+        // `2` of `_atom_site.label_entity_id` on `2 2 B`
+        assert_eq!(
+            target(definition(CIF, 18, 2, &relations)),
+            Some(("_entity.id".to_string(), 5, 0))
+        );
+        // Cursor just after `B`
+        assert_eq!(
+            target(definition(CIF, 18, 5, &relations)),
+            Some(("_struct_asym.id".to_string(), 10, 0))
+        );
+        // Quoted child value, key-value category
+        assert_eq!(
+            target(definition(CIF, 12, 34, &relations)),
+            Some(("_struct_asym.id".to_string(), 10, 0))
+        );
+        // `_struct_asym.entity_id` of row A
+        assert_eq!(
+            target(definition(CIF, 9, 2, &relations)),
+            Some(("_entity.id".to_string(), 4, 0))
+        );
+        // Parent values are not children; `_entity.id 2` of the next block is not searched
+        assert_eq!(definition(CIF, 4, 0, &relations), None);
+        assert_eq!(
+            target(definition(
+                &CIF.replace("2 non-polymer", "3 water"),
+                18,
+                2,
+                &relations
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn test_data_name_definition() {
+        let relations = Relations::mmcif();
+        let found = definition(CIF, 15, 3, &relations).unwrap();
+        assert_eq!(
+            (
+                found.data_name.as_str(),
+                found.line,
+                found.start,
+                found.length
+            ),
+            ("_entity.id", 2, 0, 10)
+        );
+    }
+
+    #[test]
+    fn test_from_dictionary() {
+        let dictionary = Document::parse(
+            "data_mmcif_pdbx.dic\nsave__entity.id\n_item.name '_entity.id'\n\
+             loop_\n_item_linked.child_name\n_item_linked.parent_name\n\
+             '_atom_site.label_entity_id' '_entity.id'\n'_struct_asym.entity_id' '_entity.id'\nsave_\n\
+             data_ddlm\nsave_child\n_definition.id '_a.parent_id'\n_name.linked_item_id '_b.id'\nsave_\n",
+        );
+        let relations = Relations::from_dictionary(&dictionary);
+        assert_eq!(
+            relations.parents("_ATOM_SITE.label_entity_id"),
+            ["_entity.id"]
+        );
+        assert_eq!(
+            relations.children("_entity.id"),
+            ["_atom_site.label_entity_id", "_struct_asym.entity_id"]
+        );
+        assert_eq!(relations.parents("_a.parent_id"), ["_b.id"]);
+        assert!(relations.parents("_entity.id").is_empty());
+    }
+}
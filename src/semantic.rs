@@ -0,0 +1,212 @@
+//! LSP semantic token encoding
+//! Packs tokens into the relative `u32` format of `textDocument/semanticTokens`:
+//! deltaLine, deltaStart, length, tokenType and tokenModifiers per token
+
+use crate::{HighlightOptions, Token, TokenKind};
+use serde::Serialize;
+use std::collections::HashMap;
+use tsify::Tsify;
+
+/// Token type names, indexed by `TokenKind as u32`
+pub const TOKEN_TYPES: [&str; 10] = [
+    "block_header",
+    "loop_keyword",
+    "category",
+    "item_name",
+    "value",
+    "text_field",
+    "comment",
+    "null",
+    "number",
+    "quoted_string",
+];
+
+/// Modifier bit of dimmed tokens; rotation `r` uses bit `r + 1`
+pub const DIMMED_MODIFIER: u32 = 1;
+
+/// Rotations that fit in the modifier bitset after `dimmed`
+const MAX_ROTATIONS: u8 = 31;
+
+/// Item name of tokens without one in `SemanticTokens::item_ids`
+pub const NO_ITEM: u32 = u32::MAX;
+
+/// Token types and modifiers in the order used by the encoding,
+/// as registered with a `SemanticTokensLegend`
+#[derive(Serialize, Tsify, Clone, Debug, PartialEq, Eq)]
+#[serde(rename = "SemanticTokenLegend")]
+pub struct Legend {
+    pub token_types: Vec<String>,
+    /// `dimmed`, then `rotation0`, `rotation1`, ... up to the rotation length
+    pub token_modifiers: Vec<String>,
+}
+
+impl Legend {
+    /// Legend for tokens highlighted with `options`
+    pub fn new(options: &HighlightOptions) -> Self {
+        let rotations = options.rotation_length.clamp(1, MAX_ROTATIONS);
+        let mut token_modifiers = vec!["dimmed".to_string()];
+        token_modifiers.extend((0..rotations).map(|r| format!("rotation{}", r)));
+        Legend {
+            token_types: TOKEN_TYPES.iter().map(|t| t.to_string()).collect(),
+            token_modifiers,
+        }
+    }
+}
+
+/// Tokens in LSP relative encoding with item names interned into a side table
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SemanticTokens {
+    /// Five integers per token; positions and lengths in UTF-16 code units
+    pub data: Vec<u32>,
+    /// Index into `item_names` per token, or `NO_ITEM`
+    pub item_ids: Vec<u32>,
+    /// Distinct item names (`_atom_site.id`) in order of first use
+    pub item_names: Vec<String>,
+}
+
+impl SemanticTokens {
+    /// Encode `tokens` of `text`; tokens must be in line and column order
+    pub fn encode(tokens: &[Token], text: &str) -> Self {
+        let mut lines = text.lines();
+        let mut next_line = 0;
+        let mut current = "";
+        Self::encode_with(tokens, |line| {
+            if line >= next_line {
+                current = lines.nth(line - next_line).unwrap_or("");
+                next_line = line + 1;
+            }
+            current
+        })
+    }
+
+    /// Encode `tokens`, looking up the text of each line with `line_text`.
+    /// Lines are requested in increasing order.
+    pub(crate) fn encode_with<'a>(
+        tokens: &[Token],
+        mut line_text: impl FnMut(usize) -> &'a str,
+    ) -> Self {
+        let mut encoded = SemanticTokens {
+            data: Vec::with_capacity(tokens.len() * 5),
+            item_ids: Vec::with_capacity(tokens.len()),
+            item_names: Vec::new(),
+        };
+        let mut interned: HashMap<&str, u32> = HashMap::new();
+        let (mut last_line, mut last_start) = (0, 0);
+
+        for token in tokens {
+            let text = line_text(token.line);
+            let start = utf16_len(text, 0, token.start);
+            let length = utf16_len(text, token.start, token.start + token.length);
+            if token.line != last_line {
+                last_start = 0;
+            }
+            encoded.data.extend([
+                (token.line - last_line) as u32,
+                start - last_start,
+                length,
+                token.kind as u32,
+                modifiers(token),
+            ]);
+            (last_line, last_start) = (token.line, start);
+
+            let item_id = match token.item_name.as_deref() {
+                Some(name) => *interned.entry(name).or_insert_with(|| {
+                    encoded.item_names.push(name.to_string());
+                    encoded.item_names.len() as u32 - 1
+                }),
+                None => NO_ITEM,
+            };
+            encoded.item_ids.push(item_id);
+        }
+        encoded
+    }
+}
+
+/// Modifier bitset of a token: `dimmed` and the rotation of rotating kinds
+fn modifiers(token: &Token) -> u32 {
+    let mut bits = if token.dimmed { DIMMED_MODIFIER } else { 0 };
+    let rotates = !matches!(
+        token.kind,
+        TokenKind::BlockHeader | TokenKind::LoopKeyword | TokenKind::Category | TokenKind::Comment
+    );
+    if rotates && token.rotation < MAX_ROTATIONS {
+        bits |= 1 << (token.rotation + 1);
+    }
+    bits
+}
+
+/// Text of a line of `lines`, empty past the end
+pub(crate) fn line_of<'a, 't>(lines: &'a [&'t str]) -> impl Fn(usize) -> &'t str + 'a {
+    |line| lines.get(line).copied().unwrap_or("")
+}
+
+/// UTF-16 length of `line[from..to]`, clamped to the line
+pub(crate) fn utf16_len(line: &str, from: usize, to: usize) -> u32 {
+    let to = to.min(line.len());
+    match line.get(from.min(to)..to) {
+        Some(part) if part.is_ascii() => part.len() as u32,
+        Some(part) => part.encode_utf16().count() as u32,
+        None => (to - from.min(to)) as u32,
+    }
+}
+
+/// UTF-16 column of byte `column` of `line`; bytes past the end of the line
+/// (a `\r` stripped from it) count one unit each
+pub(crate) fn utf16_column(line: &str, column: usize) -> usize {
+    utf16_len(line, 0, column) as usize + column.saturating_sub(line.len())
+}
+
+/// Byte offset of UTF-16 position `character` in `line`, clamped to the line
+pub(crate) fn byte_column(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn test_encode() {
+        let text = "data_x\nloop_\n_a.b\n_a.c\n'é' 1 # c\nx 2\n";
+        let tokens = parse(text).tokens;
+        let encoded = SemanticTokens::encode(&tokens, text);
+
+        assert_eq!(encoded.data.len(), tokens.len() * 5);
+        let rows: Vec<&[u32]> = encoded.data.chunks(5).collect();
+        // data_x, loop_, `_a` `.b`
+        assert_eq!(rows[0], [0, 0, 6, TokenKind::BlockHeader as u32, 0]);
+        assert_eq!(rows[1], [1, 0, 5, TokenKind::LoopKeyword as u32, 0]);
+        assert_eq!(rows[3], [0, 2, 2, TokenKind::ItemName as u32, 1 << 1]);
+        // `'é'` is 4 bytes but 3 UTF-16 units; `1` starts at character 4
+        assert_eq!(rows[6], [1, 0, 3, TokenKind::Value as u32, 1 << 1]);
+        assert_eq!(rows[7], [0, 4, 1, TokenKind::Value as u32, 1 << 2]);
+        assert_eq!(rows[8], [0, 2, 3, TokenKind::Comment as u32, 0]);
+
+        assert_eq!(encoded.item_names, ["_a.b", "_a.c"]);
+        assert_eq!(
+            encoded.item_ids[6..],
+            [0, 1, NO_ITEM, 0, 1],
+            "values share interned item names"
+        );
+        assert!(encoded.item_ids[..6].iter().all(|&id| id == NO_ITEM));
+    }
+
+    #[test]
+    fn test_legend() {
+        let legend = Legend::new(&HighlightOptions::default());
+        assert_eq!(
+            legend.token_types[TokenKind::QuotedString as usize],
+            "quoted_string"
+        );
+        assert_eq!(legend.token_modifiers.len(), 8);
+        assert_eq!(legend.token_modifiers[1], "rotation0");
+    }
+}
@@ -0,0 +1,304 @@
+//! Document symbols and a workspace-wide index of CIF files
+//! Maps data block names, category names and key values (`_chem_comp.id ATP`)
+//! to the file and position where they are defined
+
+use crate::compression::read_text;
+use crate::parser::parse_cif_internal;
+use crate::tokenizer::unquote_value;
+use crate::{HighlightOptions, TokenKind, ValueKind};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Kind of a symbol
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SymbolKind {
+    /// `data_` block or `save_` frame
+    Block,
+    /// Category (`_atom_site`), once per block
+    Category,
+    /// Value of a key item, e.g. `ATP` of `_chem_comp.id`
+    Key,
+}
+
+/// A named definition in a CIF file
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    /// Block name without prefix, category name or key value (unquoted)
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Data name of a key value (`_chem_comp.id`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_name: Option<String>,
+    /// Name of the enclosing data block; empty for data blocks and data before any header
+    pub block: String,
+    pub line: usize,
+    pub start: usize,
+    pub length: usize,
+}
+
+/// Which values are indexed as key symbols
+#[derive(Clone, Debug)]
+pub struct IndexOptions {
+    /// Data names whose values become symbols
+    pub key_items: Vec<String>,
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        IndexOptions {
+            key_items: vec!["_entry.id".to_string(), "_chem_comp.id".to_string()],
+        }
+    }
+}
+
+/// Symbols of CIF text in file order
+pub fn document_symbols(text: &str, options: &IndexOptions) -> Vec<Symbol> {
+    let result = parse_cif_internal(text, &HighlightOptions::default());
+    let lines: Vec<&str> = text.lines().collect();
+    let line_text = |line: usize| lines.get(line).copied().unwrap_or("");
+
+    let mut symbols: Vec<Symbol> = result
+        .tokens
+        .iter()
+        .filter(|t| t.kind == TokenKind::BlockHeader)
+        .filter_map(|t| {
+            let header = line_text(t.line).get(t.start..t.start + t.length)?;
+            let name = header
+                .strip_prefix("data_")
+                .or_else(|| header.strip_prefix("save_"))?;
+            (!name.is_empty()).then(|| Symbol {
+                name: name.to_string(),
+                kind: SymbolKind::Block,
+                data_name: None,
+                block: String::new(),
+                line: t.line,
+                start: t.start,
+                length: t.length,
+            })
+        })
+        .collect();
+
+    for block in &result.loops {
+        let Some(first) = block.items.first() else {
+            continue;
+        };
+        symbols.push(Symbol {
+            name: block.category_name.clone(),
+            kind: SymbolKind::Category,
+            data_name: None,
+            block: String::new(),
+            line: first.line,
+            start: first.start - block.category_name.len() - 1,
+            length: block.category_name.len(),
+        });
+
+        for (column, item) in block.items.iter().enumerate() {
+            let data_name = format!("{}.{}", block.category_name, item.name);
+            if !options.key_items.contains(&data_name) {
+                continue;
+            }
+            for data_line in &block.data_lines {
+                for range in data_line.value_ranges.iter() {
+                    if range.column_index != column || range.kind == ValueKind::TextField {
+                        continue;
+                    }
+                    let Some(raw) =
+                        line_text(data_line.line).get(range.start..range.start + range.length)
+                    else {
+                        continue;
+                    };
+                    symbols.push(Symbol {
+                        name: unquote_value(range.kind, raw).to_string(),
+                        kind: SymbolKind::Key,
+                        data_name: Some(data_name.clone()),
+                        block: String::new(),
+                        line: data_line.line,
+                        start: range.start,
+                        length: range.length,
+                    });
+                }
+            }
+        }
+    }
+
+    // Attach each symbol to the last data block header before it
+    symbols.sort_by_key(|s| (s.line, s.start));
+    let mut block = String::new();
+    for symbol in &mut symbols {
+        let is_data_block = symbol.kind == SymbolKind::Block
+            && line_text(symbol.line)[symbol.start..].starts_with("data_");
+        if is_data_block {
+            block = symbol.name.clone();
+        } else {
+            symbol.block = block.clone();
+        }
+    }
+    symbols
+}
+
+/// Whether a path looks like a CIF file, possibly compressed
+pub fn is_cif_path(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let name = [".gz", ".zst", ".bz2"]
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext))
+        .unwrap_or(&name);
+    [".cif", ".mmcif", ".dic"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+}
+
+/// Symbols of many CIF files, searchable by name
+#[derive(Clone, Debug, Default)]
+pub struct WorkspaceIndex {
+    options: IndexOptions,
+    files: BTreeMap<PathBuf, Vec<Symbol>>,
+}
+
+impl WorkspaceIndex {
+    /// Create an empty index
+    pub fn new(options: IndexOptions) -> Self {
+        WorkspaceIndex {
+            options,
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Index every CIF file below `dir`, returning the number of files indexed.
+    /// Files and subdirectories that cannot be read or decoded are skipped, and
+    /// symbolic links to directories are not followed.
+    pub fn index_dir(&mut self, dir: impl AsRef<Path>) -> io::Result<usize> {
+        let root = dir.as_ref();
+        let mut count = 0;
+        let mut pending = vec![root.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(error) if dir == root => return Err(error),
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                let path = entry.path();
+                if file_type.is_dir() {
+                    pending.push(path);
+                } else if is_cif_path(&path) && self.index_file(&path).is_ok() {
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Index or re-index a file from disk
+    pub fn index_file(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let text = read_text(std::fs::File::open(path)?)?;
+        self.update(path, &text);
+        Ok(())
+    }
+
+    /// Replace the symbols of `path` with those of `text` (e.g. an unsaved editor buffer)
+    pub fn update(&mut self, path: impl Into<PathBuf>, text: &str) {
+        let symbols = document_symbols(text, &self.options);
+        self.files.insert(path.into(), symbols);
+    }
+
+    /// Forget a file
+    pub fn remove(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    /// Symbols of an indexed file
+    pub fn symbols(&self, path: &Path) -> Option<&[Symbol]> {
+        self.files.get(path).map(|s| s.as_slice())
+    }
+
+    /// Number of indexed files
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Symbols whose name contains `query`, ignoring ASCII case.
+    /// Exact matches come first, then files in path order.
+    pub fn search(&self, query: &str) -> Vec<(&Path, &Symbol)> {
+        let query = query.to_ascii_lowercase();
+        let mut found: Vec<(&Path, &Symbol)> = self
+            .files
+            .iter()
+            .flat_map(|(path, symbols)| symbols.iter().map(move |s| (path.as_path(), s)))
+            .filter(|(_, s)| s.name.to_ascii_lowercase().contains(&query))
+            .collect();
+        found.sort_by_key(|(_, s)| !s.name.eq_ignore_ascii_case(&query));
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIGAND: &str =
+        "data_ATP\n_chem_comp.id 'ATP'\n_chem_comp.name \"ADENOSINE TRIPHOSPHATE\"\n\
+loop_\n_chem_comp_atom.comp_id\n_chem_comp_atom.atom_id\nATP PG\nATP O1G\n";
+
+    #[test]
+    fn test_document_symbols() {
+        let symbols = document_symbols(LIGAND, &IndexOptions::default());
+        let summary: Vec<(SymbolKind, &str, &str, usize, usize)> = symbols
+            .iter()
+            .map(|s| (s.kind, s.name.as_str(), s.block.as_str(), s.line, s.start))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (SymbolKind::Block, "ATP", "", 0, 0),
+                (SymbolKind::Category, "_chem_comp", "ATP", 1, 0),
+                (SymbolKind::Key, "ATP", "ATP", 1, 14),
+                (SymbolKind::Category, "_chem_comp_atom", "ATP", 4, 0),
+            ]
+        );
+        assert_eq!(symbols[2].data_name.as_deref(), Some("_chem_comp.id"));
+        assert_eq!(symbols[2].length, 5);
+    }
+
+    #[test]
+    fn test_workspace_index() {
+        let dir = std::env::temp_dir().join(format!("cif-index-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("ligands")).unwrap();
+        std::fs::write(dir.join("ligands/ATP.cif"), LIGAND).unwrap();
+        std::fs::write(dir.join("1abc.cif"), "data_1ABC\n_entry.id 1ABC\n").unwrap();
+        std::fs::write(dir.join("notes.txt"), "data_ATP\n").unwrap();
+        #[cfg(unix)]
+        let _ = std::os::unix::fs::symlink(&dir, dir.join("ligands/parent"));
+
+        let mut index = WorkspaceIndex::default();
+        assert_eq!(index.index_dir(&dir).unwrap(), 2);
+
+        let found = index.search("atp");
+        assert!(found
+            .iter()
+            .all(|(path, _)| path.ends_with("ligands/ATP.cif")));
+        assert_eq!(found.len(), 2);
+        let keys: Vec<&Symbol> = index
+            .search("1abc")
+            .into_iter()
+            .filter(|(_, s)| s.kind == SymbolKind::Key)
+            .map(|(_, s)| s)
+            .collect();
+        assert_eq!(keys[0].data_name.as_deref(), Some("_entry.id"));
+
+        index.update(dir.join("1abc.cif"), "data_2XYZ\n");
+        assert!(index.search("1abc").is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
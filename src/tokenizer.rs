@@ -1,90 +1,517 @@
 //! Tokenizer for CIF format
 //! Handles line splitting and quoted string detection
 
+use crate::ValueKind;
+use memchr::{memchr, memchr2, memchr_iter};
+
+/// Byte range of a token within a line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub is_quoted: bool,
+}
+
+/// Split a line into token spans, handling quoted strings correctly.
+/// Returns the spans and the byte offset of a trailing `#` comment, if any.
+///
+/// Scans bytes rather than chars: all delimiters are ASCII and never occur inside
+/// a multi-byte UTF-8 sequence, so spans always fall on char boundaries.
+pub fn split_spans(content: &str) -> (Vec<Span>, Option<usize>) {
+    let mut output: Vec<Span> = Vec::new();
+    let comment = split_spans_into(content.as_bytes(), &mut output);
+    (output, comment)
+}
+
+/// Like [`split_spans`] on a line of bytes, reusing `output` for the spans
+fn split_spans_into(bytes: &[u8], output: &mut Vec<Span>) -> Option<usize> {
+    output.clear();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b' ' | b'\t' => i += 1,
+            // Comment at a token start - stop processing
+            b'#' => return Some(i),
+            // Quote at a token start; an unterminated quote runs to the end of the line
+            quote @ (b'\'' | b'"') => {
+                let end = closing_quote(bytes, i + 1, quote).map_or(bytes.len(), |q| q + 1);
+                output.push(Span {
+                    start: i,
+                    end,
+                    is_quoted: true,
+                });
+                i = end;
+            }
+            // Bare token up to whitespace; a `#` inside a word is part of it
+            _ => {
+                let end = memchr2(b' ', b'\t', &bytes[i..]).map_or(bytes.len(), |n| i + n);
+                output.push(Span {
+                    start: i,
+                    end,
+                    is_quoted: false,
+                });
+                i = end;
+            }
+        }
+    }
+
+    None
+}
+
+/// Position of the quote closing a quoted string: the first `quote` at or after `from`
+/// that is followed by whitespace or the end of the line
+fn closing_quote(bytes: &[u8], from: usize, quote: u8) -> Option<usize> {
+    memchr_iter(quote, &bytes[from..])
+        .map(|n| from + n)
+        .find(|&q| matches!(bytes.get(q + 1), None | Some(b' ') | Some(b'\t')))
+}
+
 /// Split a line into tokens, handling quoted strings correctly.
 /// Returns Vec of (token_string, is_quoted)
 pub fn special_split(content: &str) -> Vec<(String, bool)> {
-    let mut output: Vec<(String, bool)> = Vec::new();
-    let mut current = String::new();
-    let mut current_quoted = false;
-    let mut in_quote = false;
-    let mut quote_char: Option<char> = None;
-    
-    let chars: Vec<char> = content.chars().collect();
-    let len = chars.len();
-    
-    for i in 0..len {
-        let c = chars[i];
-        let is_ws = c == ' ' || c == '\t';
-        
-        // Check for quote boundaries
-        if (c == '\'' || c == '"') && !in_quote {
-            // Start quote if at boundary
-            let at_start = i == 0 || chars[i - 1] == ' ' || chars[i - 1] == '\t';
-            if at_start {
-                in_quote = true;
-                quote_char = Some(c);
-                current.push(c);
-                current_quoted = true;
-                continue;
+    split_spans(content)
+        .0
+        .into_iter()
+        .map(|span| (content[span.start..span.end].to_string(), span.is_quoted))
+        .collect()
+}
+
+/// Kind of a lexeme in the token stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LexemeKind {
+    /// Unquoted word (keyword, data name or bare value)
+    Word,
+    /// Single or double quoted string
+    Quoted,
+    /// `;` delimited text field, possibly spanning several lines
+    TextField,
+    /// `#` comment up to the end of the line
+    Comment,
+}
+
+/// Token of the CIF token stream with its position in the source
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lexeme<'a> {
+    pub kind: LexemeKind,
+    /// Line of the first character
+    pub line: usize,
+    /// Byte column of the first character
+    pub start: usize,
+    /// Length in bytes on the first line
+    pub length: usize,
+    /// Last line covered (differs from `line` only for text fields)
+    pub end_line: usize,
+    /// Byte offset of the first character in the source (the opening `;` of text fields)
+    pub offset: usize,
+    /// Raw token text; for text fields the content between the delimiters
+    pub text: &'a str,
+}
+
+impl<'a> Lexeme<'a> {
+    /// The lexeme with its text as bytes
+    pub(crate) fn raw(&self) -> RawLexeme<'a> {
+        RawLexeme {
+            kind: self.kind,
+            line: self.line,
+            start: self.start,
+            length: self.length,
+            end_line: self.end_line,
+            offset: self.offset,
+            text: self.text.as_bytes(),
+        }
+    }
+}
+
+/// Raw lexeme text with the quotes of quoted strings removed
+pub(crate) fn unquote(kind: LexemeKind, text: &str) -> &str {
+    match kind {
+        LexemeKind::Quoted if text.len() >= 2 => {
+            let quote = &text[..1];
+            let inner = &text[1..];
+            inner.strip_suffix(quote).unwrap_or(inner)
+        }
+        _ => text,
+    }
+}
+
+/// Text of a classified value with the quotes of quoted strings removed
+pub(crate) fn unquote_value(kind: ValueKind, text: &str) -> &str {
+    match kind {
+        ValueKind::SingleQuoted | ValueKind::DoubleQuoted => unquote(LexemeKind::Quoted, text),
+        _ => text,
+    }
+}
+
+/// Inputs of at least this many bytes are tokenized in parallel
+#[cfg(feature = "rayon")]
+const PARALLEL_THRESHOLD: usize = 1 << 20;
+
+/// Split CIF text into a stream of lexemes, independent of line layout.
+/// With the `rayon` feature, large inputs are split into chunks that are tokenized in parallel.
+pub fn tokenize(text: &str) -> Vec<Lexeme<'_>> {
+    #[cfg(feature = "rayon")]
+    if text.len() >= PARALLEL_THRESHOLD {
+        let chunk_size = text.len() / (rayon::current_num_threads() * 4);
+        return tokenize_parallel(text, chunk_size.max(PARALLEL_THRESHOLD / 4));
+    }
+    tokenize_lines(text, 0, 0)
+}
+
+/// Tokenize chunks of about `chunk_size` bytes in parallel and concatenate them in order
+#[cfg(feature = "rayon")]
+fn tokenize_parallel(text: &str, chunk_size: usize) -> Vec<Lexeme<'_>> {
+    use rayon::prelude::*;
+
+    let starts = chunk_starts(text, chunk_size);
+    let chunks: Vec<Vec<Lexeme>> = starts
+        .par_iter()
+        .enumerate()
+        .map(|(i, &(offset, first_line))| {
+            let end = starts.get(i + 1).map_or(text.len(), |&(next, _)| next);
+            tokenize_lines(&text[offset..end], offset, first_line)
+        })
+        .collect();
+    chunks.concat()
+}
+
+/// Byte offsets and line numbers where chunks of at least `chunk_size` bytes can start.
+/// Outside text fields lexemes never span lines, so a chunk may start at any line
+/// that is not inside or delimiting a text field, also within a single long loop.
+#[cfg(feature = "rayon")]
+fn chunk_starts(text: &str, chunk_size: usize) -> Vec<(usize, usize)> {
+    let mut starts = vec![(0, 0)];
+    let mut offset = 0usize;
+    let mut in_text_field = false;
+
+    for (line_num, raw_line) in text.split_inclusive('\n').enumerate() {
+        let line_start = offset;
+        offset += raw_line.len();
+        if raw_line.starts_with(';') {
+            in_text_field = !in_text_field;
+            continue;
+        }
+        if !in_text_field && line_start - starts[starts.len() - 1].0 >= chunk_size {
+            starts.push((line_start, line_num));
+        }
+    }
+    starts
+}
+
+/// Tokenize text starting at byte `base` and line `first_line` of the source
+pub(crate) fn tokenize_lines(text: &str, base: usize, first_line: usize) -> Vec<Lexeme<'_>> {
+    lexemes(text, base, first_line).collect()
+}
+
+/// Lexemes of text starting at byte `base` and line `first_line`, produced one line at a time
+pub(crate) fn lexemes(
+    text: &str,
+    base: usize,
+    first_line: usize,
+) -> impl Iterator<Item = Lexeme<'_>> {
+    RawLexemes::new(text.as_bytes(), base, first_line).map(move |raw| raw.in_str(text))
+}
+
+/// Lexeme with its text as bytes, not yet validated as UTF-8
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawLexeme<'a> {
+    pub kind: LexemeKind,
+    pub line: usize,
+    pub start: usize,
+    pub length: usize,
+    pub end_line: usize,
+    pub offset: usize,
+    pub text: &'a [u8],
+}
+
+impl<'a> RawLexeme<'a> {
+    /// The lexeme with its text borrowed from `source`, the text it was scanned from
+    fn in_str(self, source: &'a str) -> Lexeme<'a> {
+        let start = self.text.as_ptr() as usize - source.as_ptr() as usize;
+        Lexeme {
+            kind: self.kind,
+            line: self.line,
+            start: self.start,
+            length: self.length,
+            end_line: self.end_line,
+            offset: self.offset,
+            text: &source[start..start + self.text.len()],
+        }
+    }
+}
+
+/// Streaming tokenizer over bytes, yielding the lexemes of one line at a time.
+/// Only the spans of the current line are buffered.
+pub(crate) struct RawLexemes<'a> {
+    bytes: &'a [u8],
+    /// Source offset of `bytes`
+    base: usize,
+    /// Start of the next unread line in `bytes`
+    offset: usize,
+    /// Number of the next unread line
+    line: usize,
+    /// Open text field: (line, length of the opening line, byte offset of content)
+    text_field: Option<(usize, usize, usize)>,
+    /// Current line: number, start offset and text without the line break
+    current: Option<(usize, usize, &'a [u8])>,
+    spans: Vec<Span>,
+    next_span: usize,
+    comment: Option<usize>,
+}
+
+impl<'a> RawLexemes<'a> {
+    pub(crate) fn new(bytes: &'a [u8], base: usize, first_line: usize) -> Self {
+        RawLexemes {
+            bytes,
+            base,
+            offset: 0,
+            line: first_line,
+            text_field: None,
+            current: None,
+            spans: Vec::new(),
+            next_span: 0,
+            comment: None,
+        }
+    }
+
+    /// Scan `line_text` from column `from` on as the current line
+    fn scan_line(&mut self, line: usize, line_start: usize, line_text: &'a [u8], from: usize) {
+        self.comment = split_spans_into(&line_text[from..], &mut self.spans).map(|c| c + from);
+        for span in &mut self.spans {
+            span.start += from;
+            span.end += from;
+        }
+        self.next_span = 0;
+        self.current = Some((line, line_start, line_text));
+    }
+
+    /// Next word, quoted string or comment of the current line
+    fn next_in_line(&mut self) -> Option<RawLexeme<'a>> {
+        let (line, line_start, line_text) = self.current?;
+        let (kind, start, end) = if let Some(span) = self.spans.get(self.next_span) {
+            self.next_span += 1;
+            let kind = if span.is_quoted {
+                LexemeKind::Quoted
+            } else {
+                LexemeKind::Word
+            };
+            (kind, span.start, span.end)
+        } else if let Some(comment_start) = self.comment.take() {
+            (LexemeKind::Comment, comment_start, line_text.len())
+        } else {
+            self.current = None;
+            return None;
+        };
+        Some(RawLexeme {
+            kind,
+            line,
+            start,
+            length: end - start,
+            end_line: line,
+            offset: self.base + line_start + start,
+            text: &line_text[start..end],
+        })
+    }
+}
+
+impl<'a> Iterator for RawLexemes<'a> {
+    type Item = RawLexeme<'a>;
+
+    fn next(&mut self) -> Option<RawLexeme<'a>> {
+        loop {
+            if let Some(lexeme) = self.next_in_line() {
+                return Some(lexeme);
             }
-        } else if in_quote && Some(c) == quote_char {
-            // End quote if at boundary
-            let at_end = i == len - 1 || chars.get(i + 1).map_or(true, |&nc| nc == ' ' || nc == '\t');
-            if at_end {
-                current.push(c);
-                in_quote = false;
-                quote_char = None;
+            if self.offset >= self.bytes.len() {
+                // Unterminated text field runs to the end of the text
+                let (start_line, length, content_start) = self.text_field.take()?;
+                return Some(RawLexeme {
+                    kind: LexemeKind::TextField,
+                    line: start_line,
+                    start: 0,
+                    length,
+                    end_line: self.line - 1,
+                    offset: self.base + content_start - 1,
+                    text: trim_line_break(&self.bytes[content_start..]),
+                });
+            }
+
+            let rest = &self.bytes[self.offset..];
+            let line_start = self.offset;
+            self.offset += memchr(b'\n', rest).map_or(rest.len(), |n| n + 1);
+            let line_num = self.line;
+            self.line += 1;
+            let line_text = trim_line_break(&self.bytes[line_start..self.offset]);
+
+            if line_text.first() == Some(&b';') {
+                if let Some((start_line, length, content_start)) = self.text_field.take() {
+                    // End of text field: content excludes the final line break.
+                    // The rest of the closing line is tokenized after the field.
+                    self.scan_line(line_num, line_start, line_text, 1);
+                    let content = &self.bytes[content_start..line_start.max(content_start)];
+                    return Some(RawLexeme {
+                        kind: LexemeKind::TextField,
+                        line: start_line,
+                        start: 0,
+                        length,
+                        end_line: line_num,
+                        offset: self.base + content_start - 1,
+                        text: trim_line_break(content),
+                    });
+                }
+                self.text_field = Some((line_num, line_text.len(), line_start + 1));
                 continue;
             }
-        }
-        
-        if !in_quote && is_ws {
-            // Whitespace outside quote - end current token
-            if !current.is_empty() {
-                output.push((current, current_quoted));
-                current = String::new();
-                current_quoted = false;
+            if self.text_field.is_some() {
+                continue;
             }
-        } else if !in_quote && c == '#' {
-            // Comment - stop processing
-            break;
-        } else {
-            // Regular character
-            current.push(c);
+            self.scan_line(line_num, line_start, line_text, 0);
         }
     }
-    
-    // Push final token if non-empty
-    if !current.is_empty() {
-        output.push((current, current_quoted));
+}
+
+/// Kind and raw bytes of the value lexeme starting at byte `offset`, as `tokenize` would
+/// produce it. Lets callers keep only offsets and decode values on demand.
+pub(crate) fn lexeme_at(bytes: &[u8], offset: usize) -> (LexemeKind, &[u8]) {
+    let rest = &bytes[offset..];
+    let at_line_start = offset == 0 || bytes[offset - 1] == b'\n';
+    if at_line_start && rest.first() == Some(&b';') {
+        // Text field up to the next line starting with `;`
+        let content = match memchr::memmem::find(rest, b"\n;") {
+            Some(end) => trim_line_break(&rest[1..end + 1]),
+            None => trim_line_break(&rest[1..]),
+        };
+        return (LexemeKind::TextField, content);
+    }
+
+    let line_end = memchr(b'\n', rest).map_or(rest.len(), |n| n + 1);
+    let line = trim_line_break(&rest[..line_end]);
+    match line.first() {
+        Some(&quote) if quote == b'\'' || quote == b'"' => {
+            let end = closing_quote(line, 1, quote).map_or(line.len(), |q| q + 1);
+            (LexemeKind::Quoted, &line[..end])
+        }
+        _ => {
+            let end = memchr2(b' ', b'\t', line).unwrap_or(line.len());
+            (LexemeKind::Word, &line[..end])
+        }
     }
-    
-    output
+}
+
+/// Remove a trailing `\n` or `\r\n`
+fn trim_line_break(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n")
+        .map_or(line, |l| l.strip_suffix(b"\r").unwrap_or(l))
 }
 
 /// Check if a token is a data name (starts with _ and is not quoted)
-pub fn is_data_name(token: &str, is_quoted: bool) -> bool {
-    !is_quoted && token.starts_with('_')
+pub fn is_data_name(token: impl AsRef<[u8]>, is_quoted: bool) -> bool {
+    !is_quoted && token.as_ref().first() == Some(&b'_')
+}
+
+/// Split a data name into its category and item at the first dot,
+/// e.g. `_atom_site.Cartn_x` into `_atom_site` and `Cartn_x`.
+/// Returns `None` for CIF1 names without a dot (`_cell_length_a`) and for
+/// names with an empty category or item. Any other characters are accepted,
+/// covering DDLm names and the non-ASCII names allowed by CIF2.
+pub fn split_data_name(name: &str) -> Option<(&str, &str)> {
+    let dot = name.find('.')?;
+    let (category, item) = (&name[..dot], &name[dot + 1..]);
+    (category.len() > 1 && category.starts_with('_') && !item.is_empty())
+        .then_some((category, item))
 }
 
 /// Check if a token is a loop keyword
-pub fn is_loop_keyword(token: &str, is_quoted: bool) -> bool {
-    !is_quoted && token == "loop_"
+pub fn is_loop_keyword(token: impl AsRef<[u8]>, is_quoted: bool) -> bool {
+    !is_quoted && token.as_ref() == b"loop_"
 }
 
 /// Check if a token is a data/save/global keyword
-pub fn is_block_keyword(token: &str, is_quoted: bool) -> bool {
+pub fn is_block_keyword(token: impl AsRef<[u8]>, is_quoted: bool) -> bool {
     if is_quoted {
         return false;
     }
-    token == "global_" || token.starts_with("data_") || token.starts_with("save_")
+    let token = token.as_ref();
+    token == b"global_" || token.starts_with(b"data_") || token.starts_with(b"save_")
+}
+
+/// Classify a value token as split by `special_split`
+pub fn classify_value(token: &str, is_quoted: bool) -> ValueKind {
+    if is_quoted {
+        return if token.starts_with('"') {
+            ValueKind::DoubleQuoted
+        } else {
+            ValueKind::SingleQuoted
+        };
+    }
+    match token {
+        "?" => ValueKind::Unknown,
+        "." => ValueKind::Inapplicable,
+        _ => classify_number(token).unwrap_or(ValueKind::Bare),
+    }
+}
+
+/// Classify a CIF number, e.g. `12`, `-1.5e3` or `1.234(5)`
+fn classify_number(token: &str) -> Option<ValueKind> {
+    let bytes = token.as_bytes();
+    let mut i = 0;
+    let mut is_float = false;
+
+    if matches!(bytes.first(), Some(b'+') | Some(b'-')) {
+        i += 1;
+    }
+    let int_digits = count_digits(&bytes[i..]);
+    i += int_digits;
+    let mut frac_digits = 0;
+    if bytes.get(i) == Some(&b'.') {
+        is_float = true;
+        i += 1;
+        frac_digits = count_digits(&bytes[i..]);
+        i += frac_digits;
+    }
+    if int_digits + frac_digits == 0 {
+        return None;
+    }
+    if matches!(bytes.get(i), Some(b'e') | Some(b'E')) {
+        is_float = true;
+        i += 1;
+        if matches!(bytes.get(i), Some(b'+') | Some(b'-')) {
+            i += 1;
+        }
+        let exp_digits = count_digits(&bytes[i..]);
+        if exp_digits == 0 {
+            return None;
+        }
+        i += exp_digits;
+    }
+    // Optional standard uncertainty, e.g. (5)
+    if bytes.get(i) == Some(&b'(') {
+        i += 1;
+        let su_digits = count_digits(&bytes[i..]);
+        i += su_digits;
+        if su_digits == 0 || bytes.get(i) != Some(&b')') {
+            return None;
+        }
+        i += 1;
+        return (i == bytes.len()).then_some(ValueKind::FloatWithSu);
+    }
+    if i != bytes.len() {
+        None
+    } else if is_float {
+        Some(ValueKind::Float)
+    } else {
+        Some(ValueKind::Integer)
+    }
+}
+
+fn count_digits(bytes: &[u8]) -> usize {
+    bytes.iter().take_while(|b| b.is_ascii_digit()).count()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+
     #[test]
     fn test_simple_split() {
         let result = special_split("foo bar baz");
@@ -93,7 +520,7 @@ mod tests {
         assert_eq!(result[1], ("bar".to_string(), false));
         assert_eq!(result[2], ("baz".to_string(), false));
     }
-    
+
     #[test]
     fn test_quoted_string() {
         let result = special_split("'hello world' test");
@@ -101,7 +528,7 @@ mod tests {
         assert_eq!(result[0], ("'hello world'".to_string(), true));
         assert_eq!(result[1], ("test".to_string(), false));
     }
-    
+
     #[test]
     fn test_comment() {
         let result = special_split("foo bar # comment");
@@ -109,22 +536,139 @@ mod tests {
         assert_eq!(result[0], ("foo".to_string(), false));
         assert_eq!(result[1], ("bar".to_string(), false));
     }
-    
+
+    #[test]
+    fn test_quote_boundaries() {
+        // Quotes only open at a token start and only close before whitespace;
+        // `#` only starts a comment at a token start
+        let result = special_split("O5' 'it's ok' \"é x\" a#b #c");
+        assert_eq!(
+            result,
+            vec![
+                ("O5'".to_string(), false),
+                ("'it's ok'".to_string(), true),
+                ("\"é x\"".to_string(), true),
+                ("a#b".to_string(), false),
+            ]
+        );
+        // Unterminated quote runs to the end of the line
+        assert_eq!(split_spans("x 'a b#").0[1].end, 7);
+        assert_eq!(split_spans("x 'a' # c").1, Some(6));
+    }
+
     #[test]
     fn test_data_name() {
         assert!(is_data_name("_atom_site.id", false));
         assert!(!is_data_name("_atom_site.id", true));
         assert!(!is_data_name("atom_site", false));
     }
-    
+
+    #[test]
+    fn test_split_data_name() {
+        assert_eq!(split_data_name("_atom_site.id"), Some(("_atom_site", "id")));
+        assert_eq!(
+            split_data_name("_symmetry.space_group_name_H-M"),
+            Some(("_symmetry", "space_group_name_H-M"))
+        );
+        assert_eq!(
+            split_data_name("_refln.F_squared_meas"),
+            Some(("_refln", "F_squared_meas"))
+        );
+        // CIF2 names may use any non-whitespace characters
+        assert_eq!(split_data_name("_größe.wert"), Some(("_größe", "wert")));
+        assert_eq!(split_data_name("_cell_length_a"), None);
+        assert_eq!(split_data_name("_a."), None);
+        assert_eq!(split_data_name("_.b"), None);
+    }
+
     #[test]
     fn test_keywords() {
         assert!(is_loop_keyword("loop_", false));
         assert!(!is_loop_keyword("loop_", true));
-        
+
         assert!(is_block_keyword("data_1ABC", false));
         assert!(is_block_keyword("save_test", false));
         assert!(is_block_keyword("global_", false));
         assert!(!is_block_keyword("data_1ABC", true));
     }
+
+    #[test]
+    fn test_value_kinds() {
+        assert_eq!(classify_value("?", false), ValueKind::Unknown);
+        assert_eq!(classify_value(".", false), ValueKind::Inapplicable);
+        assert_eq!(classify_value("'?'", true), ValueKind::SingleQuoted);
+        assert_eq!(classify_value("\"a b\"", true), ValueKind::DoubleQuoted);
+
+        assert_eq!(classify_value("12", false), ValueKind::Integer);
+        assert_eq!(classify_value("-1.5e3", false), ValueKind::Float);
+        assert_eq!(classify_value(".5", false), ValueKind::Float);
+        assert_eq!(classify_value("1.234(5)", false), ValueKind::FloatWithSu);
+        assert_eq!(classify_value("1A", false), ValueKind::Bare);
+        assert_eq!(classify_value("1.2(", false), ValueKind::Bare);
+        assert_eq!(classify_value("ATOM", false), ValueKind::Bare);
+    }
+
+    #[test]
+    fn test_tokenize_text_field() {
+        let lexemes = tokenize("_a.x\r\n;line 1\r\nline 2\r\n;\r\n_a.y 'q' # c\n");
+        let kinds: Vec<LexemeKind> = lexemes.iter().map(|l| l.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                LexemeKind::Word,
+                LexemeKind::TextField,
+                LexemeKind::Word,
+                LexemeKind::Quoted,
+                LexemeKind::Comment,
+            ]
+        );
+        assert_eq!(lexemes[1].text, "line 1\r\nline 2");
+        assert_eq!((lexemes[1].line, lexemes[1].end_line), (1, 3));
+        assert_eq!((lexemes[3].line, lexemes[3].start), (4, 5));
+
+        // Values after the closing `;` belong to the same line
+        let lexemes = tokenize(
+            ";t
+; A 'b c' # d
+2
+",
+        );
+        let texts: Vec<(&str, usize, usize)> =
+            lexemes.iter().map(|l| (l.text, l.line, l.start)).collect();
+        assert_eq!(
+            texts,
+            [
+                ("t", 0, 0),
+                ("A", 1, 2),
+                ("'b c'", 1, 4),
+                ("# d", 1, 10),
+                ("2", 2, 0)
+            ]
+        );
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_tokenize_parallel() {
+        let mut text = String::from("data_x\n_entry.id 1ABC\nloop_\n_atom_site.id\n_atom_site.x\n");
+        for i in 0..200 {
+            text.push_str(&format!("{} {}.5\n", i, i));
+            if i % 50 == 0 {
+                // Lines inside text fields are not chunk boundaries
+                text.push_str(";\nnot a boundary\nloop_\n;\n");
+            }
+        }
+        text.push_str("_struct.title 'end'\n");
+
+        // The single loop is split into many chunks
+        let starts = chunk_starts(&text, 64);
+        assert!(starts.len() > 20);
+        for &(offset, line) in &starts {
+            let rest = &text[offset..];
+            assert!(!rest.starts_with("not") && !rest.starts_with("loop_"));
+            assert!(!rest.starts_with(";"));
+            assert_eq!(text[..offset].matches('\n').count(), line);
+        }
+        assert_eq!(tokenize_parallel(&text, 64), tokenize_lines(&text, 0, 0));
+    }
 }
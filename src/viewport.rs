@@ -0,0 +1,220 @@
+//! Viewport-limited highlighting for large files
+//! Indexes line starts and parser checkpoints so tokens of a line range can be
+//! produced without tokenizing the whole document
+
+use crate::highlight::{apply_highlight_from, HighlightMode};
+use crate::parser::{checkpoints, parse_from, Checkpoint};
+use crate::semantic::SemanticTokens;
+use crate::{HighlightOptions, Token};
+use memchr::memchr_iter;
+
+/// Line-start and loop-state index of a CIF text for `tokens_for_lines` queries.
+/// Holds no tokens; building it walks the text once.
+#[derive(Clone, Debug)]
+pub struct TokenIndex {
+    options: HighlightOptions,
+    /// Byte offset of the start of each line
+    line_starts: Vec<usize>,
+    /// Parser states at line starts, in line order
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl TokenIndex {
+    /// Index `text` for highlighting with `options`
+    pub fn new(text: &str, options: &HighlightOptions) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(memchr_iter(b'\n', text.as_bytes()).map(|n| n + 1));
+        TokenIndex {
+            options: *options,
+            line_starts,
+            checkpoints: checkpoints(text, options),
+        }
+    }
+
+    /// Number of lines of the indexed text
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Tokens on lines `start_line..=end_line` of `text`, the text this index was built from.
+    /// Parsing resumes at the nearest checkpoint before `start_line`, so the cost depends on
+    /// the range and the checkpoint interval rather than the size of the file.
+    pub fn tokens_for_lines(&self, text: &str, start_line: usize, end_line: usize) -> Vec<Token> {
+        if start_line > end_line || start_line >= self.line_starts.len() {
+            return Vec::new();
+        }
+        let checkpoint = self.checkpoint_before(start_line);
+        let start = self.line_starts[checkpoint.line];
+        let end = self
+            .line_starts
+            .get(end_line + 1)
+            .map_or(text.len(), |&n| n.min(text.len()));
+        let source = &text[start..end];
+
+        let mut result = parse_from(source, start, checkpoint, &self.options);
+        apply_highlight_from(
+            &mut result,
+            source,
+            checkpoint.line,
+            &self.options,
+            checkpoint.groups.as_ref(),
+        );
+        result.tokens.retain(|t| t.line >= start_line);
+        result.tokens
+    }
+
+    /// Tokens on lines `start_line..=end_line` of `text` in LSP semantic token encoding
+    pub fn semantic_tokens_for_lines(
+        &self,
+        text: &str,
+        start_line: usize,
+        end_line: usize,
+    ) -> SemanticTokens {
+        let tokens = self.tokens_for_lines(text, start_line, end_line);
+        SemanticTokens::encode_with(&tokens, |line| self.line(text, line))
+    }
+
+    /// Text of `line` without its line break, empty past the end
+    pub(crate) fn line<'a>(&self, text: &'a str, line: usize) -> &'a str {
+        let start = self
+            .line_starts
+            .get(line)
+            .map_or(text.len(), |&n| n.min(text.len()));
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .map_or(text.len(), |&n| n.min(text.len()));
+        let raw = &text[start..end];
+        let raw = raw.strip_suffix('\n').unwrap_or(raw);
+        raw.strip_suffix('\r').unwrap_or(raw)
+    }
+
+    /// Last checkpoint at or before `line`. Structure-aware modes continue the group
+    /// numbering stored in the checkpoint, so they skip those inside an `_atom_site` row.
+    fn checkpoint_before(&self, line: usize) -> &Checkpoint {
+        let column_mode = self.options.mode == HighlightMode::Column;
+        let candidates = &self.checkpoints[..self.checkpoints.partition_point(|c| c.line <= line)];
+        candidates
+            .iter()
+            .rev()
+            .find(|c| column_mode || c.groups.is_some())
+            .unwrap_or(&self.checkpoints[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_with_options;
+    use std::fmt::Write;
+
+    fn atom_site(rows: usize) -> String {
+        let mut text = String::from(
+            "data_test\n_entry.id TEST\n_struct.title\n;\nA long\ntitle\n;\nloop_\n\
+             _atom_site.id\n_atom_site.label_asym_id\n_atom_site.occupancy\n_atom_site.note\n",
+        );
+        for i in 0..rows {
+            let chain = ["A", "B", "C"][i / 300 % 3];
+            if i % 97 == 0 {
+                writeln!(text, "{i} {chain} 0.5\n;\nnote {i}\n;").unwrap();
+            } else {
+                writeln!(text, "{i} {chain}\n1.0 'n {i}' # row").unwrap();
+            }
+        }
+        text.push_str("_exptl.method 'X-RAY DIFFRACTION'\n");
+        text
+    }
+
+    fn assert_same_tokens(text: &str, options: &HighlightOptions) {
+        let full = parse_with_options(text, options).tokens;
+        let index = TokenIndex::new(text, options);
+        assert!(index.checkpoints.len() > 3);
+        for (start, end) in [(0, 5), (3, 40), (1000, 1100), (1500, 1520), (1790, 5000)] {
+            let expected: Vec<String> = full
+                .iter()
+                .filter(|t| (start..=end).contains(&t.line))
+                .map(|t| format!("{t:?}"))
+                .collect();
+            let tokens: Vec<String> = index
+                .tokens_for_lines(text, start, end)
+                .iter()
+                .map(|t| format!("{t:?}"))
+                .collect();
+            assert_eq!(tokens, expected, "lines {start}..={end}");
+        }
+    }
+
+    #[test]
+    fn test_matches_full_parse() {
+        let text = atom_site(900);
+        assert_same_tokens(&text, &HighlightOptions::default());
+        assert_same_tokens(
+            &text,
+            &HighlightOptions {
+                distinguish_value_kinds: true,
+                dim_partial_occupancy: true,
+                ..Default::default()
+            },
+        );
+        for mode in [HighlightMode::Chain, HighlightMode::Residue] {
+            assert_same_tokens(
+                &text,
+                &HighlightOptions {
+                    mode,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn test_groups_resume_near_range() {
+        let text = atom_site(900);
+        let options = HighlightOptions {
+            mode: HighlightMode::Chain,
+            ..Default::default()
+        };
+        let index = TokenIndex::new(&text, &options);
+        let checkpoint = index.checkpoint_before(1500);
+        assert!(
+            checkpoint.line > 1500 - 300,
+            "resumed at {}",
+            checkpoint.line
+        );
+        assert!(checkpoint.groups.is_some());
+    }
+
+    #[test]
+    fn test_value_after_text_field() {
+        let text = format!(
+            "data_a\n_x.note\n;t\n{}; _y.id 1\n_y.name a\n",
+            "line\n".repeat(300)
+        );
+        let closing = 303;
+        let options = HighlightOptions::default();
+        let full = parse_with_options(&text, &options).tokens;
+        let index = TokenIndex::new(&text, &options);
+        assert!(index.checkpoints.iter().all(|c| c.line != closing));
+        let expected: Vec<String> = full
+            .iter()
+            .filter(|t| t.line >= closing)
+            .map(|t| format!("{t:?}"))
+            .collect();
+        let tokens: Vec<String> = index
+            .tokens_for_lines(&text, closing, closing + 1)
+            .iter()
+            .map(|t| format!("{t:?}"))
+            .collect();
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let text = atom_site(3);
+        let index = TokenIndex::new(&text, &HighlightOptions::default());
+        assert!(index.tokens_for_lines(&text, 500, 600).is_empty());
+        assert!(index.tokens_for_lines(&text, 5, 4).is_empty());
+        let empty = TokenIndex::new("", &HighlightOptions::default());
+        assert!(empty.tokens_for_lines("", 0, 0).is_empty());
+    }
+}
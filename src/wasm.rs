@@ -1,44 +1,679 @@
 //! WASM bindings for CIF parser
 
-use crate::parse;
+use crate::diagnostics::{diagnostics_with, utf16_diagnostics, Diagnostic, Fix};
+use crate::incremental::IncrementalParse;
+use crate::parser::parse_cif_internal;
+use crate::refactor::{
+    rename_at_with, rename_category_with, rename_chain_with, renumber_residues_with, utf16_edits,
+    ChainId, EditError, TextEdit,
+};
+use crate::relations::{definition_with, Definition, Relations};
+use crate::semantic::{byte_column, utf16_len, Legend, SemanticTokens};
+use crate::writer::write_cif;
+use crate::{
+    decode_bytes, parse_with_limits, Document, HighlightMode, HighlightOptions, ParseError,
+    ParseLimits, ParseResult, Token, TokenIndex,
+};
+use serde::Serialize;
+use std::cell::OnceCell;
 use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
 
-/// CIF Parser for WASM
+#[wasm_bindgen(typescript_custom_section)]
+const TS_DECLARATIONS: &'static str = r#"
+export type CifParserOptions = HighlightOptions & ParseLimits;
+
+/** Error thrown by `CifParser`, told apart by `name` */
+export interface CifError extends Error {
+    name:
+        | "InvalidUtf8Error"
+        | "DecompressError"
+        | "InputTooLargeError"
+        | "LimitExceededError"
+        | "EditError"
+        | "InvalidOptionError";
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "CifParserOptions | undefined")]
+    pub type JsParserOptions;
+    #[wasm_bindgen(typescript_type = "ParseResult")]
+    pub type JsParseResult;
+    #[wasm_bindgen(typescript_type = "Token[]")]
+    pub type JsTokens;
+    #[wasm_bindgen(typescript_type = "LoopBlock[]")]
+    pub type JsLoopBlocks;
+    #[wasm_bindgen(typescript_type = "string[]")]
+    pub type JsStrings;
+    #[wasm_bindgen(typescript_type = "SemanticTokenLegend")]
+    pub type JsSemanticTokenLegend;
+    #[wasm_bindgen(typescript_type = "Progress")]
+    pub type JsProgress;
+    #[wasm_bindgen(typescript_type = "Diagnostic[]")]
+    pub type JsDiagnostics;
+    #[wasm_bindgen(typescript_type = "Fix[]")]
+    pub type JsFixes;
+    #[wasm_bindgen(typescript_type = "TextEdit[]")]
+    pub type JsTextEdits;
+    #[wasm_bindgen(typescript_type = "Definition | undefined")]
+    pub type JsDefinition;
+    #[wasm_bindgen(typescript_type = "(progress: Progress) => void")]
+    pub type JsProgressCallback;
+}
+
+/// Bytes parsed between clock checks in `step` and between `parse_with_progress` callbacks
+const STEP_BYTES: usize = 256 * 1024;
+
+/// Thrown as a JS `Error` whose `name` is the error class, e.g. `InputTooLargeError`
+impl From<ParseError> for JsValue {
+    fn from(error: ParseError) -> Self {
+        let js_error = js_sys::Error::new(&error.to_string());
+        js_error.set_name(error.name());
+        js_error.into()
+    }
+}
+
+/// Thrown as a JS `Error` named `EditError` when an edit operation is refused
+impl From<EditError> for JsValue {
+    fn from(error: EditError) -> Self {
+        let js_error = js_sys::Error::new(&error.to_string());
+        js_error.set_name("EditError");
+        js_error.into()
+    }
+}
+
+/// JS `Error` named `InvalidOptionError` for constructor options or arguments that are not valid
+fn invalid_option(message: impl std::fmt::Display) -> JsValue {
+    let js_error = js_sys::Error::new(&message.to_string());
+    js_error.set_name("InvalidOptionError");
+    js_error.into()
+}
+
+/// Error of `step` and `finish` without a preceding `start`
+fn not_started() -> JsValue {
+    JsError::new("no parse in progress; call start first").into()
+}
+
+/// Serialize `value` as the declared JS type `T`
+fn to_js<T: JsCast>(value: &impl Serialize) -> Result<T, JsValue> {
+    Ok(serde_wasm_bindgen::to_value(value)?.unchecked_into())
+}
+
+/// CIF Parser for WASM.
+/// Holds the document loaded with `load`, indexed once per version and queried in slices.
+/// Methods throw a `CifError` when input is invalid or exceeds the configured limits.
 #[wasm_bindgen]
-pub struct CifParser;
+#[derive(Default)]
+pub struct CifParser {
+    options: HighlightOptions,
+    limits: ParseLimits,
+    /// Text of the loaded document
+    text: String,
+    /// Line and checkpoint index of `text` for viewport token queries
+    index: Option<TokenIndex>,
+    /// Parse result of `text` for position queries and edits, built on the first one
+    result: OnceCell<ParseResult>,
+    /// Diagnostics of `text` in UTF-16 columns, built on the first query
+    diagnostics: OnceCell<Vec<Diagnostic>>,
+    /// Document model of `text`, built on the first value query
+    document: OnceCell<Document>,
+    /// Cooperative parse begun with `start`
+    incremental: Option<IncrementalParse>,
+    /// Relations of the dictionary set with `set_dictionary`; built-in mmCIF ones if unset
+    dictionary: Option<Relations>,
+}
+
+/// Tokens in LSP semantic token encoding, ready for a `SemanticTokens` result
+#[wasm_bindgen]
+pub struct EncodedTokens(SemanticTokens);
+
+#[wasm_bindgen]
+impl EncodedTokens {
+    /// `Uint32Array` of deltaLine, deltaStart, length, tokenType and tokenModifiers per token
+    #[wasm_bindgen(getter)]
+    pub fn data(&self) -> Vec<u32> {
+        self.0.data.clone()
+    }
+
+    /// `Uint32Array` of indices into `item_names` per token; `0xFFFFFFFF` for none
+    #[wasm_bindgen(getter)]
+    pub fn item_ids(&self) -> Vec<u32> {
+        self.0.item_ids.clone()
+    }
+
+    /// Distinct item names referenced by `item_ids`
+    #[wasm_bindgen(getter)]
+    pub fn item_names(&self) -> Result<JsStrings, JsValue> {
+        to_js(&self.0.item_names)
+    }
+}
 
 #[wasm_bindgen]
 impl CifParser {
-    /// Create a new CIF parser instance
+    /// Create a new CIF parser instance.
+    /// `options` is an optional object with the highlighting options `rotation_length`,
+    /// `distinguish_value_kinds`, `mode` and `dim_partial_occupancy` and the limits
+    /// `max_input_bytes` and `max_tokens`; missing fields use the defaults.
     #[wasm_bindgen(constructor)]
-    pub fn new() -> Self {
-        CifParser
+    pub fn new(options: JsParserOptions) -> Result<CifParser, JsValue> {
+        let options: JsValue = options.into();
+        if options.is_undefined() || options.is_null() {
+            return Ok(CifParser::default());
+        }
+        Ok(CifParser {
+            options: serde_wasm_bindgen::from_value(options.clone()).map_err(invalid_option)?,
+            limits: serde_wasm_bindgen::from_value(options).map_err(invalid_option)?,
+            ..Default::default()
+        })
+    }
+
+    /// Load a document, replacing the previous one
+    #[wasm_bindgen]
+    pub fn load(&mut self, text: String) -> Result<(), JsValue> {
+        self.limits.check_size(text.len())?;
+        self.text = text;
+        self.reparse();
+        Ok(())
+    }
+
+    /// Apply an edit to the loaded document: replace the range from
+    /// (`start_line`, `start_character`) to (`end_line`, `end_character`) with `new_text`.
+    /// Characters count UTF-16 code units, as in VS Code and LSP positions.
+    #[wasm_bindgen]
+    pub fn apply_edit(
+        &mut self,
+        start_line: usize,
+        start_character: usize,
+        end_line: usize,
+        end_character: usize,
+        new_text: &str,
+    ) -> Result<(), JsValue> {
+        let start = byte_offset(&self.text, start_line, start_character);
+        let end = byte_offset(&self.text, end_line, end_character).max(start);
+        self.limits
+            .check_size(self.text.len() - (end - start) + new_text.len())?;
+        self.text.replace_range(start..end, new_text);
+        self.reparse();
+        Ok(())
+    }
+
+    /// Tokens of the loaded document on lines `start_line..=end_line`, e.g. the visible range.
+    /// Only that range is tokenized, so highlighting stays cheap for huge files.
+    #[wasm_bindgen]
+    pub fn tokens_for_lines(
+        &self,
+        start_line: usize,
+        end_line: usize,
+    ) -> Result<JsTokens, JsValue> {
+        to_js(&self.tokens_on_lines(start_line, end_line))
+    }
+
+    /// Same as `tokens_for_lines`, under its earlier name
+    #[wasm_bindgen]
+    pub fn tokens_in_range(&self, start_line: usize, end_line: usize) -> Result<JsTokens, JsValue> {
+        self.tokens_for_lines(start_line, end_line)
+    }
+
+    /// Tokens of the loaded document on lines `start_line..=end_line` in LSP semantic
+    /// token encoding (for a `DocumentRangeSemanticTokensProvider`)
+    #[wasm_bindgen]
+    pub fn semantic_tokens_for_lines(&self, start_line: usize, end_line: usize) -> EncodedTokens {
+        let encoded = self
+            .index
+            .as_ref()
+            .map(|index| index.semantic_tokens_for_lines(&self.text, start_line, end_line))
+            .unwrap_or_default();
+        EncodedTokens(encoded)
+    }
+
+    /// Value of `category.item` in a row of the loaded document's first block,
+    /// or undefined if there is none. The category may be given with or without `_`.
+    #[wasm_bindgen]
+    pub fn get_value(&self, category: &str, item: &str, row: usize) -> Option<String> {
+        let name = format!("_{}", category.trim_start_matches('_'));
+        let block = self.document().first_block()?;
+        Some(block.category(&name)?.value(item, row)?.text.clone())
+    }
+
+    /// Use the parent/child links (`_item_linked`) of a dictionary for `definition`
+    /// instead of the built-in mmCIF table
+    #[wasm_bindgen]
+    pub fn set_dictionary(&mut self, text: &str) -> Result<(), JsValue> {
+        self.limits.check_size(text.len())?;
+        self.dictionary = Some(Relations::from_dictionary(&Document::parse(text)));
+        Ok(())
+    }
+
+    /// Go-to-definition in the loaded document: the parent row's key value of the child
+    /// value at (`line`, `character`), e.g. the `_entity` row of an
+    /// `_atom_site.label_entity_id`, or the parent of a child data name.
+    /// Columns count UTF-16 code units; undefined if there is no parent.
+    #[wasm_bindgen]
+    pub fn definition(&self, line: usize, character: usize) -> Result<JsDefinition, JsValue> {
+        to_js(&self.find_definition(line, character))
+    }
+
+    /// Problems of the loaded document, each with its quick fix if there is one.
+    /// Positions and edits count UTF-16 code units.
+    #[wasm_bindgen]
+    pub fn diagnostics(&self) -> Result<JsDiagnostics, JsValue> {
+        to_js(&self.diagnostic_list())
+    }
+
+    /// Quick fixes of the problems on lines `start_line..=end_line` of the loaded document,
+    /// for a `CodeActionProvider`
+    #[wasm_bindgen]
+    pub fn code_actions(&self, start_line: usize, end_line: usize) -> Result<JsFixes, JsValue> {
+        let fixes: Vec<&Fix> = self
+            .diagnostic_list()
+            .iter()
+            .filter(|d| (start_line..=end_line).contains(&d.line))
+            .filter_map(|d| d.fix.as_ref())
+            .collect();
+        to_js(&fixes)
+    }
+
+    /// Edits renaming chain `old` to `new` in every chain ID column of the loaded document;
+    /// label chain IDs (`label_asym_id`) unless `auth` is set. Edits are single-line
+    /// replacements in UTF-16 columns and are not applied; pass them to `apply_edit`
+    /// from last to first, or to the editor. Throws an `EditError` if `new` is already used.
+    #[wasm_bindgen]
+    pub fn rename_chain(&self, old: &str, new: &str, auth: bool) -> Result<JsTextEdits, JsValue> {
+        let chain = if auth { ChainId::Auth } else { ChainId::Label };
+        self.edits(rename_chain_with(
+            self.parse_result(),
+            &|line| self.line_text(line),
+            chain,
+            old,
+            new,
+        )?)
+    }
+
+    /// Edits adding `offset` to the author residue numbers of the loaded document,
+    /// in every category, limited to author chain `chain` if given.
+    /// Throws an `EditError` if a renumbered value would overflow.
+    #[wasm_bindgen]
+    pub fn renumber_residues(
+        &self,
+        chain: Option<String>,
+        offset: i32,
+    ) -> Result<JsTextEdits, JsValue> {
+        self.edits(renumber_residues_with(
+            self.parse_result(),
+            &|line| self.line_text(line),
+            chain.as_deref(),
+            offset as i64,
+        )?)
+    }
+
+    /// Edits renaming category `old` to `new` (both with `_`) in the loaded document
+    #[wasm_bindgen]
+    pub fn rename_category(&self, old: &str, new: &str) -> Result<JsTextEdits, JsValue> {
+        self.edits(rename_category_with(self.parse_result(), old, new)?)
+    }
+
+    /// Edits renaming the chain ID or category at (`line`, `character`) of the loaded
+    /// document, for a rename provider; empty if there is nothing to rename there
+    #[wasm_bindgen]
+    pub fn rename_at(
+        &self,
+        line: usize,
+        character: usize,
+        new_name: &str,
+    ) -> Result<JsTextEdits, JsValue> {
+        let column = byte_column(self.line_text(line), character);
+        self.edits(rename_at_with(
+            self.parse_result(),
+            &|line| self.line_text(line),
+            line,
+            column,
+            new_name,
+        )?)
+    }
+
+    /// Category names of the loaded document's first block, in file order
+    #[wasm_bindgen]
+    pub fn category_names(&self) -> Result<JsStrings, JsValue> {
+        to_js(&self.category_list())
     }
 
     /// Parse CIF text and return JSON result
     #[wasm_bindgen]
-    pub fn parse(&self, text: &str) -> JsValue {
-        let result = parse(text);
-        serde_wasm_bindgen::to_value(&result).unwrap_or(JsValue::NULL)
+    pub fn parse(&self, text: &str) -> Result<JsParseResult, JsValue> {
+        to_js(&self.parse_text(text, &self.options)?)
+    }
+
+    /// Begin a cooperative parse of `text`, replacing any unfinished one.
+    /// Call `step` until it reports `done`, yielding to the event loop in between, then `finish`.
+    #[wasm_bindgen]
+    pub fn start(&mut self, text: String) -> Result<(), JsValue> {
+        self.incremental = Some(IncrementalParse::new(text, &self.options, &self.limits)?);
+        Ok(())
+    }
+
+    /// Parse for about `budget_ms` milliseconds and return the progress
+    #[wasm_bindgen]
+    pub fn step(&mut self, budget_ms: f64) -> Result<JsProgress, JsValue> {
+        let parse = self.incremental.as_mut().ok_or_else(not_started)?;
+        let deadline = js_sys::Date::now() + budget_ms;
+        loop {
+            let progress = parse.step(STEP_BYTES)?;
+            if progress.done || js_sys::Date::now() >= deadline {
+                return to_js(&progress);
+            }
+        }
+    }
+
+    /// Complete the cooperative parse, parsing what is left, and return the result.
+    /// Structure-aware highlighting and converting the result to JS happen here in one
+    /// blocking call; run it in a Web Worker if that pause matters.
+    #[wasm_bindgen]
+    pub fn finish(&mut self) -> Result<JsParseResult, JsValue> {
+        let parse = self.incremental.take().ok_or_else(not_started)?;
+        to_js(&parse.finish()?)
+    }
+
+    /// Parse CIF text, calling `callback` with the progress after each chunk
+    /// (e.g. to post it from a Web Worker)
+    #[wasm_bindgen]
+    pub fn parse_with_progress(
+        &self,
+        text: String,
+        callback: JsProgressCallback,
+    ) -> Result<JsParseResult, JsValue> {
+        let callback: js_sys::Function = callback.unchecked_into();
+        let mut parse = IncrementalParse::new(text, &self.options, &self.limits)?;
+        loop {
+            let progress = parse.step(STEP_BYTES)?;
+            callback.call1(&JsValue::NULL, &serde_wasm_bindgen::to_value(&progress)?)?;
+            if progress.done {
+                break;
+            }
+        }
+        to_js(&parse.finish()?)
+    }
+
+    /// Decompress CIF bytes (`Uint8Array`, e.g. a fetched `.cif.gz`) into text.
+    /// Plain text is returned as is.
+    #[wasm_bindgen]
+    pub fn decompress(&self, bytes: &[u8]) -> Result<String, JsValue> {
+        Ok(decode_bytes(bytes, &self.limits)?)
+    }
+
+    /// Parse CIF bytes (`Uint8Array`), decompressing them if needed
+    #[wasm_bindgen]
+    pub fn parse_bytes(&self, bytes: &[u8]) -> Result<JsParseResult, JsValue> {
+        self.parse(&decode_bytes(bytes, &self.limits)?)
     }
 
     /// Parse CIF text and return only tokens (for syntax highlighting)
     #[wasm_bindgen]
-    pub fn parse_tokens(&self, text: &str) -> JsValue {
-        let result = parse(text);
-        serde_wasm_bindgen::to_value(&result.tokens).unwrap_or(JsValue::NULL)
+    pub fn parse_tokens(&self, text: &str) -> Result<JsTokens, JsValue> {
+        to_js(&self.parse_text(text, &self.options)?.tokens)
+    }
+
+    /// Parse CIF text and return tokens in LSP semantic token encoding
+    /// (for a `DocumentSemanticTokensProvider`)
+    #[wasm_bindgen]
+    pub fn parse_semantic_tokens(&self, text: &str) -> Result<EncodedTokens, JsValue> {
+        let result = self.parse_text(text, &self.options)?;
+        Ok(EncodedTokens(SemanticTokens::encode(&result.tokens, text)))
+    }
+
+    /// Token types and modifiers of the semantic token encoding,
+    /// `{ token_types: [...], token_modifiers: [...] }`
+    #[wasm_bindgen]
+    pub fn semantic_token_legend(&self) -> Result<JsSemanticTokenLegend, JsValue> {
+        to_js(&Legend::new(&self.options))
+    }
+
+    /// Parse CIF text and return tokens with `_atom_site` rows colored by `mode`
+    /// (`column`, `chain`, `residue`, `entity`, `group_PDB` or `alt_id`);
+    /// throws an `InvalidOptionError` for any other mode
+    #[wasm_bindgen]
+    pub fn parse_tokens_with_mode(
+        &self,
+        text: &str,
+        mode: &str,
+        dim_partial_occupancy: bool,
+    ) -> Result<JsTokens, JsValue> {
+        let options = HighlightOptions {
+            mode: HighlightMode::from_name(mode)
+                .ok_or_else(|| invalid_option(format!("unknown highlight mode {:?}", mode)))?,
+            dim_partial_occupancy,
+            ..self.options
+        };
+        to_js(&self.parse_text(text, &options)?.tokens)
+    }
+
+    /// Convert CIF text to an mmJSON string
+    #[wasm_bindgen]
+    pub fn to_mmjson(&self, text: &str) -> Result<String, JsValue> {
+        self.limits.check_size(text.len())?;
+        Ok(Document::parse(text).to_mmjson_string())
+    }
+
+    /// Merge duplicate data blocks, save frames, categories and data names and return the
+    /// rewritten CIF text
+    #[wasm_bindgen]
+    pub fn merge_duplicates(&self, text: &str) -> Result<String, JsValue> {
+        self.limits.check_size(text.len())?;
+        let mut document = Document::parse(text);
+        document.merge_duplicates();
+        Ok(write_cif(&document))
     }
 
     /// Parse CIF text and return only loops (for structure analysis)
     #[wasm_bindgen]
-    pub fn parse_loops(&self, text: &str) -> JsValue {
-        let result = parse(text);
-        serde_wasm_bindgen::to_value(&result.loops).unwrap_or(JsValue::NULL)
+    pub fn parse_loops(&self, text: &str) -> Result<JsLoopBlocks, JsValue> {
+        to_js(&self.parse_text(text, &self.options)?.loops)
     }
 }
 
-impl Default for CifParser {
-    fn default() -> Self {
-        Self::new()
+impl CifParser {
+    fn parse_text(
+        &self,
+        text: &str,
+        options: &HighlightOptions,
+    ) -> Result<ParseResult, ParseError> {
+        parse_with_limits(text, options, &self.limits)
+    }
+
+    fn reparse(&mut self) {
+        self.index = Some(TokenIndex::new(&self.text, &self.options));
+        self.result = OnceCell::new();
+        self.diagnostics = OnceCell::new();
+        self.document = OnceCell::new();
+    }
+
+    fn parse_result(&self) -> &ParseResult {
+        self.result
+            .get_or_init(|| parse_cif_internal(&self.text, &HighlightOptions::default()))
+    }
+
+    /// Text of a line of the loaded document without its line break, empty past the end
+    fn line_text(&self, line: usize) -> &str {
+        self.index
+            .as_ref()
+            .map_or("", |index| index.line(&self.text, line))
+    }
+
+    fn document(&self) -> &Document {
+        self.document.get_or_init(|| Document::parse(&self.text))
+    }
+
+    fn tokens_on_lines(&self, start_line: usize, end_line: usize) -> Vec<Token> {
+        self.index
+            .as_ref()
+            .map(|index| index.tokens_for_lines(&self.text, start_line, end_line))
+            .unwrap_or_default()
+    }
+
+    fn diagnostic_list(&self) -> &[Diagnostic] {
+        self.diagnostics.get_or_init(|| {
+            let found = diagnostics_with(&self.text, self.parse_result());
+            utf16_diagnostics(found, &|line| self.line_text(line))
+        })
+    }
+
+    fn edits(&self, edits: Vec<TextEdit>) -> Result<JsTextEdits, JsValue> {
+        to_js(&utf16_edits(edits, &|line| self.line_text(line)))
+    }
+
+    fn find_definition(&self, line: usize, character: usize) -> Option<Definition> {
+        let column = byte_column(self.line_text(line), character);
+        let builtin;
+        let relations = match &self.dictionary {
+            Some(relations) => relations,
+            None => {
+                builtin = Relations::mmcif();
+                &builtin
+            }
+        };
+        let found = definition_with(
+            self.parse_result(),
+            &|line| self.line_text(line),
+            line,
+            column,
+            relations,
+        )?;
+        let target = self.line_text(found.line);
+        Some(Definition {
+            start: utf16_len(target, 0, found.start) as usize,
+            length: utf16_len(target, found.start, found.start + found.length) as usize,
+            ..found
+        })
+    }
+
+    fn category_list(&self) -> Vec<&str> {
+        self.document()
+            .first_block()
+            .map(|b| b.categories.iter().map(|c| c.name.as_str()).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Byte offset of a line and UTF-16 character position, clamped to the line and text
+fn byte_offset(text: &str, line: usize, character: usize) -> usize {
+    let mut line_start = 0;
+    for _ in 0..line {
+        match text[line_start..].find('\n') {
+            Some(n) => line_start += n + 1,
+            None => return text.len(),
+        }
+    }
+    let line_text = text[line_start..].split('\n').next().unwrap_or("");
+    let line_text = line_text.strip_suffix('\r').unwrap_or(line_text);
+    line_start + byte_column(line_text, character)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CIF: &str =
+        "data_x\n_entry.id 1ABC\nloop_\n_atom_site.id\n_atom_site.type_symbol\n1 N\n2 C\n";
+
+    fn loaded(text: &str) -> CifParser {
+        let mut parser = CifParser::default();
+        parser.load(text.to_string()).unwrap();
+        parser
+    }
+
+    #[test]
+    fn test_queries() {
+        let parser = loaded(CIF);
+        assert_eq!(parser.category_list(), ["_entry", "_atom_site"]);
+        assert_eq!(
+            parser.get_value("atom_site", "type_symbol", 1).as_deref(),
+            Some("C")
+        );
+        assert_eq!(parser.get_value("_entry", "id", 0).as_deref(), Some("1ABC"));
+        assert_eq!(parser.get_value("_entry", "id", 1), None);
+
+        let tokens = parser.tokens_on_lines(5, 6);
+        assert_eq!(tokens.len(), 4);
+        assert!(tokens.iter().all(|t| (5..=6).contains(&t.line)));
+        assert!(parser.tokens_on_lines(20, 30).is_empty());
+    }
+
+    #[test]
+    fn test_apply_edit() {
+        let mut parser = loaded(CIF);
+        // Replace `N` on line 5 and append a row after the last line
+        parser.apply_edit(5, 2, 5, 3, "O").unwrap();
+        parser.apply_edit(7, 0, 7, 0, "3 S\n").unwrap();
+        assert_eq!(
+            parser.get_value("atom_site", "type_symbol", 0).as_deref(),
+            Some("O")
+        );
+        assert_eq!(
+            parser.get_value("atom_site", "type_symbol", 2).as_deref(),
+            Some("S")
+        );
+
+        // Delete the loop across lines
+        parser.apply_edit(2, 0, 7, 0, "").unwrap();
+        assert_eq!(parser.category_list(), ["_entry"]);
+    }
+
+    #[test]
+    fn test_definition() {
+        let mut parser = loaded(
+            "data_x\n_entity.id 'ü1'\nloop_\n_atom_site.id\n_atom_site.label_entity_id\n1 'ü1'\n",
+        );
+        let found = parser.find_definition(5, 3).unwrap();
+        assert_eq!((found.line, found.start, found.length), (1, 11, 4));
+        assert_eq!(found.data_name, "_entity.id");
+
+        parser
+            .set_dictionary("data_d\nsave_s\n_item_linked.child_name _atom_site.id\n_item_linked.parent_name _entity.id\nsave_\n")
+            .unwrap();
+        assert_eq!(parser.find_definition(5, 3), None);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut parser = loaded("data_x\r\nloop_\n_a.x\n_a.y\n1 2\n3\n");
+        let found = parser.diagnostic_list();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[1].line, 5);
+        assert_eq!(found[1].fix.as_ref().unwrap().edits[0].new_text, " ?");
+
+        // Cached per version; an edit invalidates them
+        parser.apply_edit(5, 1, 5, 1, " 4").unwrap();
+        assert_eq!(parser.diagnostic_list().len(), 1);
+    }
+
+    #[test]
+    fn test_crlf_fix() {
+        let mut parser = loaded("data_x\r\n_a.b 'é'\r\n_a.c 1\r\n");
+        let fix = parser.diagnostic_list()[0].fix.clone().unwrap();
+        for edit in fix.edits.iter().rev() {
+            parser
+                .apply_edit(
+                    edit.line,
+                    edit.start,
+                    edit.end_line,
+                    edit.end,
+                    &edit.new_text,
+                )
+                .unwrap();
+        }
+        assert_eq!(parser.text, "data_x\n_a.b 'é'\n_a.c 1\n");
+        assert!(parser.diagnostic_list().is_empty());
+    }
+
+    #[test]
+    fn test_utf16_positions() {
+        // `é` is one UTF-16 unit but two bytes; `😀` is two units and four bytes
+        let text = "_a.x 'é😀b'\r\n_a.y 2";
+        assert_eq!(byte_offset(text, 0, 7), 8);
+        assert_eq!(byte_offset(text, 0, 9), 12);
+        assert_eq!(byte_offset(text, 0, 100), 14);
+        assert_eq!(byte_offset(text, 1, 5), 21);
+        assert_eq!(byte_offset(text, 5, 0), text.len());
     }
 }
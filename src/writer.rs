@@ -0,0 +1,168 @@
+//! CIF writer
+//! Serializes a document back to CIF text with correct quoting
+
+use crate::{Category, DataBlock, Document, Value, ValueKind};
+
+/// Write a document as CIF text
+pub fn write_cif(doc: &Document) -> String {
+    let mut out = String::new();
+    for block in &doc.blocks {
+        write_block(&mut out, block, "data_");
+    }
+    out
+}
+
+fn write_block(out: &mut String, block: &DataBlock, prefix: &str) {
+    out.push_str(prefix);
+    out.push_str(&block.name);
+    out.push_str("\n#\n");
+    for category in &block.categories {
+        write_category(out, category);
+        out.push_str("#\n");
+    }
+    for frame in &block.frames {
+        write_block(out, frame, "save_");
+        out.push_str("save_\n#\n");
+    }
+}
+
+/// Write a single category as key-value pairs or as a loop
+pub fn write_category(out: &mut String, category: &Category) {
+    let names: Vec<String> = category
+        .items
+        .iter()
+        .map(|item| category.data_name(item))
+        .collect();
+
+    if !category.is_loop && category.row_count() <= 1 {
+        let width = names.iter().map(|n| n.len()).max().unwrap_or(0);
+        for (name, column) in names.iter().zip(&category.columns) {
+            let value = column.first().map_or("?".to_string(), format_value);
+            if value.starts_with(';') {
+                out.push_str(&format!("{}\n{}\n", name, value));
+            } else {
+                out.push_str(&format!("{:width$} {}\n", name, value, width = width));
+            }
+        }
+        return;
+    }
+
+    out.push_str("loop_\n");
+    for name in &names {
+        out.push_str(name);
+        out.push('\n');
+    }
+    for row in 0..category.row_count() {
+        let mut line = String::new();
+        for column in &category.columns {
+            let value = column.get(row).map_or("?".to_string(), format_value);
+            if value.starts_with(';') {
+                if !line.is_empty() {
+                    out.push_str(&line);
+                    out.push('\n');
+                    line.clear();
+                }
+                out.push_str(&value);
+                out.push('\n');
+            } else {
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(&value);
+            }
+        }
+        if !line.is_empty() {
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+}
+
+/// Format a value as it must appear in CIF text.
+/// Multi-line values become `;` text fields (starting with `;`, no trailing newline).
+pub fn format_value(value: &Value) -> String {
+    match value.kind {
+        ValueKind::Unknown => return "?".to_string(),
+        ValueKind::Inapplicable => return ".".to_string(),
+        _ => {}
+    }
+    let text = value.text.as_str();
+    if value.kind == ValueKind::TextField || text.contains(['\n', '\r']) {
+        return format!(";{}\n;", text);
+    }
+    // Keep the original quoting of quoted values
+    let quoted = matches!(
+        value.kind,
+        ValueKind::SingleQuoted | ValueKind::DoubleQuoted
+    );
+    if !quoted && !needs_quotes(text, value.kind) {
+        return text.to_string();
+    }
+    let fits = |quote: char| {
+        !text.contains(format!("{} ", quote).as_str())
+            && !text.contains(format!("{}\t", quote).as_str())
+            && !text.ends_with(quote)
+    };
+    let preferred = if value.kind == ValueKind::DoubleQuoted {
+        ['"', '\'']
+    } else {
+        ['\'', '"']
+    };
+    match preferred.into_iter().find(|&q| fits(q)) {
+        Some(quote) => format!("{}{}{}", quote, text, quote),
+        None => format!(";{}\n;", text),
+    }
+}
+
+/// Whether text must be quoted to be read back as the same value
+pub fn needs_quotes(text: &str, kind: ValueKind) -> bool {
+    if text.is_empty() || text.contains([' ', '\t']) {
+        return true;
+    }
+    if text.starts_with(['_', '#', '$', '\'', '"', ';', '[', ']']) {
+        return true;
+    }
+    // Quoted ? and . are real values, not nulls
+    if (text == "?" || text == ".") && !kind.is_null() {
+        return true;
+    }
+    let lower = text.to_ascii_lowercase();
+    lower.starts_with("data_")
+        || lower.starts_with("save_")
+        || lower == "loop_"
+        || lower == "global_"
+        || lower == "stop_"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quoted(text: &str) -> Value {
+        Value {
+            text: text.to_string(),
+            kind: ValueKind::SingleQuoted,
+        }
+    }
+
+    #[test]
+    fn test_format_value() {
+        assert_eq!(format_value(&Value::new("ATOM")), "ATOM");
+        assert_eq!(format_value(&Value::new("?")), "?");
+        assert_eq!(format_value(&quoted("?")), "'?'");
+        assert_eq!(format_value(&quoted("a b")), "'a b'");
+        assert_eq!(format_value(&quoted("it's")), "'it's'");
+        assert_eq!(format_value(&quoted("it' s")), "\"it' s\"");
+        assert_eq!(format_value(&quoted("_name")), "'_name'");
+        assert_eq!(format_value(&quoted("data_x")), "'data_x'");
+        assert_eq!(format_value(&quoted("a\nb")), ";a\nb\n;");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let cif = "data_x\n_a.id 1\n_a.title\n;multi\nline\n;\nloop_\n_b.id\n_b.name\n1 'x y'\n2 \"q\"\n3 ?\n";
+        let doc = Document::parse(cif);
+        let written = write_cif(&doc);
+        assert_eq!(Document::parse(&written), doc);
+    }
+}